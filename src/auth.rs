@@ -1,25 +1,42 @@
 use regex::Regex;
+use std::io::{self, BufRead};
 
+use crate::utils::run_command;
 use crate::{AuthType, Url};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Auth {
     Bearer(String),
     Basic(String, Option<String>),
+    Digest(String, String),
 }
 
 impl Auth {
-    pub fn new(auth: Option<String>, auth_type: Option<AuthType>, url: &Url) -> Option<Auth> {
+    pub fn new(
+        auth: Option<String>,
+        auth_type: Option<AuthType>,
+        url: &Url,
+    ) -> Result<Option<Auth>, &'static str> {
+        Auth::with_prompt(auth, auth_type, url, prompt_password)
+    }
+
+    // `prompt` asks for the password when --auth only has a username
+    fn with_prompt(
+        auth: Option<String>,
+        auth_type: Option<AuthType>,
+        url: &Url,
+        prompt: impl FnOnce(&str) -> Result<String, &'static str>,
+    ) -> Result<Option<Auth>, &'static str> {
         let auth_type = auth_type.unwrap_or(AuthType::Basic);
         let auth = match auth {
             Some(auth) if !auth.is_empty() => auth,
             _ => {
-                return None;
+                return Ok(None);
             }
         };
 
         match auth_type {
-            AuthType::Basic => {
+            AuthType::Basic | AuthType::Digest => {
                 let re = Regex::new(r"^(.+?):(.*)$").unwrap();
                 if let Some(cap) = re.captures(&auth) {
                     let username = cap[1].to_string();
@@ -28,16 +45,148 @@ impl Auth {
                     } else {
                         None
                     };
-                    Some(Auth::Basic(username, password))
+                    match auth_type {
+                        AuthType::Digest => {
+                            Ok(Some(Auth::Digest(username, password.unwrap_or_default())))
+                        }
+                        _ => Ok(Some(Auth::Basic(username, password))),
+                    }
                 } else {
                     let username = auth;
-                    let prompt =
-                        format!("http: password for {}@{}: ", username, url.host().unwrap());
-                    let password = rpassword::read_password_from_tty(Some(&prompt)).unwrap();
-                    Some(Auth::Basic(username, Some(password)))
+                    let host = url.host().ok_or("The URL has no host")?;
+                    let password = prompt(&format!("http: password for {}@{}: ", username, host))?;
+                    match auth_type {
+                        AuthType::Digest => Ok(Some(Auth::Digest(username, password))),
+                        _ => Ok(Some(Auth::Basic(username, Some(password)))),
+                    }
                 }
             }
-            AuthType::Bearer => Some(Auth::Bearer(auth)),
+            AuthType::Bearer => Ok(Some(Auth::Bearer(auth))),
         }
     }
 }
+
+fn prompt_password(prompt: &str) -> Result<String, &'static str> {
+    if !has_terminal() {
+        return Err("Password is missing from --auth and there is no terminal to prompt for it");
+    }
+    rpassword::read_password_from_tty(Some(prompt))
+        .map_err(|_| "Failed to read password from the terminal")
+}
+
+// rpassword prompts on the controlling terminal rather than stdin, so a
+// redirected stdin is fine as long as /dev/tty can be opened
+#[cfg(unix)]
+fn has_terminal() -> bool {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .is_ok()
+}
+
+#[cfg(not(unix))]
+fn has_terminal() -> bool {
+    true
+}
+
+// Only the first line is consumed so credentials never end up in argv
+pub fn read_auth_from_stdin() -> Result<String, &'static str> {
+    let mut line = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .map_err(|_| "Failed to read credentials from stdin")?;
+    let line = line.trim_end_matches(&['\r', '\n'][..]);
+    if line.is_empty() {
+        return Err("--auth-stdin expects credentials on the first line of stdin");
+    }
+    Ok(line.to_string())
+}
+
+pub fn run_bearer_command(command: &str) -> Result<String, String> {
+    let token = run_command(command).map_err(|err| format!("--bearer-command: {}", err))?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err("--bearer-command printed no token".to_string());
+    }
+    Ok(token.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(auth: &str, auth_type: AuthType) -> Result<Option<Auth>, &'static str> {
+        let url = Url::new("http://example.com/".to_string(), None).unwrap();
+        let prompt = |prompt: &str| {
+            assert_eq!(prompt, "http: password for user@example.com: ");
+            Ok("prompted".to_string())
+        };
+        Auth::with_prompt(Some(auth.to_string()), Some(auth_type), &url, prompt)
+    }
+
+    fn user(password: Option<&str>) -> Auth {
+        Auth::Basic("user".to_string(), password.map(str::to_string))
+    }
+
+    #[test]
+    fn auth_types() {
+        assert_eq!(
+            auth("user:pass", AuthType::Basic),
+            Ok(Some(user(Some("pass"))))
+        );
+        assert_eq!(auth("user:", AuthType::Basic), Ok(Some(user(None))));
+        assert_eq!(
+            auth("token", AuthType::Bearer),
+            Ok(Some(Auth::Bearer("token".to_string())))
+        );
+        assert_eq!(
+            auth("user:a:b", AuthType::Digest),
+            Ok(Some(Auth::Digest("user".to_string(), "a:b".to_string())))
+        );
+        let url = Url::new("http://example.com/".to_string(), None).unwrap();
+        assert_eq!(Auth::new(Some(String::new()), None, &url), Ok(None));
+    }
+
+    #[test]
+    fn prompt_for_missing_password() {
+        assert_eq!(
+            auth("user", AuthType::Basic),
+            Ok(Some(user(Some("prompted"))))
+        );
+        assert_eq!(
+            auth("user", AuthType::Digest),
+            Ok(Some(Auth::Digest(
+                "user".to_string(),
+                "prompted".to_string()
+            )))
+        );
+
+        let url = Url::new("http://example.com/".to_string(), None).unwrap();
+        let no_terminal = |_: &str| Err("no terminal");
+        assert_eq!(
+            Auth::with_prompt(Some("user".to_string()), None, &url, no_terminal),
+            Err("no terminal")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bearer_command() {
+        assert_eq!(
+            run_bearer_command("echo '  token  '"),
+            Ok("token".to_string())
+        );
+        assert_eq!(
+            run_bearer_command("true"),
+            Err("--bearer-command printed no token".to_string())
+        );
+        let err = run_bearer_command("exit 3").unwrap_err();
+        assert!(
+            err.starts_with("--bearer-command: \"exit 3\" failed"),
+            "{}",
+            err
+        );
+    }
+}
@@ -1,9 +1,13 @@
 use std::str::FromStr;
+use std::time::Duration;
 
 use regex::Regex;
 use structopt::clap::{arg_enum, Error, ErrorKind, Result};
 use structopt::StructOpt;
 
+use crate::printer::WRAP_INDENT;
+use crate::template;
+
 // Following doc comments were copy-pasted from HTTPie
 /// Yet another HTTPie clone
 #[derive(StructOpt, Debug)]
@@ -17,45 +21,370 @@ pub struct Opt {
     #[structopt(long)]
     pub offline: bool,
 
+    /// Check the URL, request items, files and credentials and report every problem
+    /// without sending the request.
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+
     /// (default) Data items from the command line are serialized as a JSON object.
-    #[structopt(short = "j", long)]
+    /// A body given with --body, --body-template or --raw is sent as application/json.
+    #[structopt(short = "j", long, conflicts_with = "form")]
     pub json: bool,
 
     /// Data items from the command line are serialized as form fields.
     #[structopt(short = "f", long)]
     pub form: bool,
 
+    /// Send the contents of a file as the request body, e.g. --body @data.json.
+    /// The file is streamed rather than read into memory, use --body @- to stream stdin.
+    /// Options that send or hash the body again, such as --retries or --aws-sigv4, read it up front.
+    /// Use --body @clipboard to send the clipboard when built with the clipboard feature.
+    #[structopt(long)]
+    pub body: Option<String>,
+
+    /// Render a template file with {{name}} placeholders and send it as the request body.
+    #[structopt(long = "body-template", value_name = "FILE", conflicts_with = "body")]
+    pub body_template: Option<String>,
+
+    /// Send the environment variables whose names start with PREFIX as a JSON object.
+    #[structopt(long = "body-env", value_name = "PREFIX", conflicts_with_all = &["body", "body-template"])]
+    pub body_env: Option<String>,
+
+    /// Send STRING as the request body, as text/plain unless a Content-Type header is given.
+    #[structopt(long, value_name = "STRING", conflicts_with_all = &["body", "body-template", "body-env"])]
+    pub raw: Option<String>,
+
+    /// Send a GraphQL request with QUERY, or with the query read from @FILE or from stdin with @-.
+    /// Data items become the query's variables.
+    #[structopt(long, value_name = "QUERY", conflicts_with_all = &["body", "body-template", "body-env", "raw", "form"])]
+    pub graphql: Option<String>,
+
+    /// JSON file with the values for --body-template placeholders.
+    #[structopt(long = "data-file", value_name = "FILE", requires = "body-template")]
+    pub data_file: Option<String>,
+
+    /// Replace placeholders without a value in --data-file with nothing instead of failing.
+    #[structopt(long = "allow-missing", requires = "body-template")]
+    pub allow_missing: bool,
+
+    /// Accept trailing commas and single-quoted strings in := and :=@ values.
+    #[structopt(long = "json-lenient")]
+    pub json_lenient: bool,
+
+    /// Allow field=@!COMMAND request items, which use the output of COMMAND as the value.
+    #[structopt(long = "allow-exec")]
+    pub allow_exec: bool,
+
+    /// Convert a YAML or TOML --body file to JSON before sending it.
+    #[structopt(long = "as-json", requires = "body")]
+    pub as_json: bool,
+
+    /// Send a User-Agent picked at random from the lines of this file.
+    #[structopt(long = "user-agent-file", value_name = "FILE")]
+    pub user_agent_file: Option<String>,
+
+    /// Compare the JSON request body with this file and fail without sending if they differ.
+    #[structopt(long = "diff-request", value_name = "FILE")]
+    pub diff_request: Option<String>,
+
     /// Specify the auth mechanism.
     #[structopt(short = "A", long = "auth-type", possible_values = &AuthType::variants(), case_insensitive = true)]
     pub auth_type: Option<AuthType>,
 
+    /// Credentials for the chosen auth mechanism: USER[:PASS] for basic and digest, TOKEN for bearer.
     #[structopt(short = "a", long)]
     pub auth: Option<String>,
 
+    /// Read the --auth credentials from the first line of stdin instead of the command line.
+    #[structopt(long = "auth-stdin", conflicts_with_all = &["auth", "batch"])]
+    pub auth_stdin: bool,
+
+    /// Run a shell command and send its output as a bearer token, for tokens that expire quickly.
+    #[structopt(long = "bearer-command", value_name = "COMMAND", conflicts_with_all = &["auth", "auth-stdin", "auth-type"])]
+    pub bearer_command: Option<String>,
+
+    /// Sign the request with AWS Signature Version 4 for SERVICE:REGION, e.g. s3:us-east-1.
+    #[structopt(
+        long = "aws-sigv4",
+        value_name = "SERVICE:REGION",
+        conflicts_with_all = &["auth", "auth-stdin", "bearer-command"]
+    )]
+    pub aws_sigv4: Option<String>,
+
+    /// Access key id used by --aws-sigv4.
+    #[structopt(
+        long = "aws-access-key-id",
+        env = "AWS_ACCESS_KEY_ID",
+        hide_env_values = true
+    )]
+    pub aws_access_key_id: Option<String>,
+
+    /// Secret access key used by --aws-sigv4.
+    #[structopt(
+        long = "aws-secret-access-key",
+        env = "AWS_SECRET_ACCESS_KEY",
+        hide_env_values = true
+    )]
+    pub aws_secret_access_key: Option<String>,
+
+    /// Session token used by --aws-sigv4 with temporary credentials.
+    #[structopt(
+        long = "aws-session-token",
+        env = "AWS_SESSION_TOKEN",
+        hide_env_values = true
+    )]
+    pub aws_session_token: Option<String>,
+
     /// Controls output processing.
     #[structopt(long, possible_values = &Pretty::variants(), case_insensitive = true)]
     pub pretty: Option<Pretty>,
 
+    /// Add a comment with the type of each value to formatted JSON output.
+    #[structopt(long = "annotate-types")]
+    pub annotate_types: bool,
+
+    /// Shorten JSON string values longer than N characters in formatted output.
+    #[structopt(long = "truncate-values", value_name = "N")]
+    pub truncate_values: Option<usize>,
+
+    /// Show CBOR and MessagePack responses as binary data instead of decoding them to JSON.
+    #[structopt(long = "no-binary-decode")]
+    pub no_binary_decode: bool,
+
+    /// Show binary request and response bodies as a hex dump instead of a notice.
+    #[structopt(long)]
+    pub hexdump: bool,
+
+    /// Maximum number of bytes to show with --hexdump.
+    #[structopt(long = "hexdump-limit", default_value = "512")]
+    pub hexdump_limit: usize,
+
+    /// Print the HTTP messages as raw bytes with CRLF line endings instead of formatting them.
+    /// Header names are printed in lowercase as reqwest normalizes them.
+    /// Compressed bodies are printed as received, without decompressing them.
+    #[structopt(long = "raw-output")]
+    pub raw_output: bool,
+
+    /// Print only the values of this response header, one per line. Can be repeated.
+    #[structopt(long = "print-header", number_of_values = 1)]
+    pub print_header: Vec<String>,
+
+    /// Wrap long header lines: none, auto (to the terminal width when printing to a terminal)
+    /// or a fixed number of columns.
+    #[structopt(long, value_name = "none|auto|COLUMNS")]
+    pub wrap: Option<Wrap>,
+
+    /// Print the cookies set by the response as a table after its headers.
+    #[structopt(long = "show-cookies")]
+    pub show_cookies: bool,
+
     /// Output coloring style.
     #[structopt(short = "s", long = "style", possible_values = &Theme::variants(), case_insensitive = true)]
     pub theme: Option<Theme>,
 
+    /// Skip verification of the server's TLS certificate.
+    #[structopt(short = "k", long)]
+    pub insecure: bool,
+
+    /// Check whether the server's certificate has been revoked.
+    /// Not supported by the native-tls backend yahc is built with, so this currently fails.
+    #[structopt(long = "check-revocation", conflicts_with = "insecure")]
+    pub check_revocation: bool,
+
+    /// Oldest TLS version to accept: tls1.0, tls1.1, tls1.2 or tls1.3.
+    #[structopt(long = "tls-min", value_name = "VERSION")]
+    pub tls_min: Option<TlsVersion>,
+
+    /// Newest TLS version to accept: tls1.0, tls1.1, tls1.2 or tls1.3.
+    #[structopt(long = "tls-max", value_name = "VERSION")]
+    pub tls_max: Option<TlsVersion>,
+
+    /// Follow redirects, up to --max-redirects. This is the default, -L is accepted as in curl.
+    #[structopt(short = "L", long, overrides_with = "no-follow")]
+    pub follow: bool,
+
+    /// Print redirect responses instead of following them.
+    #[structopt(long = "no-follow", overrides_with = "follow")]
+    pub no_follow: bool,
+
+    /// How the method and body are carried over when following a redirect.
+    #[structopt(long = "redirect-policy", possible_values = &RedirectPolicy::variants(), case_insensitive = true)]
+    pub redirect_policy: Option<RedirectPolicy>,
+
+    /// Maximum number of redirects to follow.
+    #[structopt(long = "max-redirects", default_value = "10")]
+    pub max_redirects: usize,
+
+    /// Initial HTTP/2 stream-level flow control window size in bytes.
+    #[structopt(long = "http2-initial-window-size", parse(try_from_str = parse_window_size))]
+    pub http2_initial_window_size: Option<u32>,
+
+    /// Initial HTTP/2 connection-level flow control window size in bytes.
+    /// The maximum number of concurrent streams and keepalive pings can't be set,
+    /// the HTTP client has no options for them.
+    #[structopt(long = "http2-initial-connection-window-size", parse(try_from_str = parse_window_size))]
+    pub http2_initial_connection_window_size: Option<u32>,
+
+    /// Fail if connecting to the server takes longer than this many seconds.
+    /// yahc exits with 3 when connecting times out and with 2 when reading the response does.
+    #[structopt(long = "connect-timeout", value_name = "SECONDS", parse(try_from_str = parse_seconds))]
+    pub connect_timeout: Option<Duration>,
+
+    /// Close pooled connections that have been idle for this many seconds.
+    #[structopt(long = "pool-idle-timeout", value_name = "SECONDS", parse(try_from_str = parse_seconds))]
+    pub pool_idle_timeout: Option<Duration>,
+
+    /// Maximum number of idle connections kept open per host.
+    #[structopt(long = "pool-max-idle", value_name = "N")]
+    pub pool_max_idle: Option<usize>,
+
+    /// Abort if no data is received for this many seconds while reading the response body.
+    #[structopt(long = "stall-timeout", value_name = "SECONDS", parse(try_from_str = parse_seconds))]
+    pub stall_timeout: Option<Duration>,
+
+    /// Give up if the request, including reading the response body, takes longer than this many seconds.
+    /// Every attempt made by --retries gets the full timeout.
+    #[structopt(long, value_name = "SECONDS", parse(try_from_str = parse_seconds))]
+    pub timeout: Option<Duration>,
+
+    /// Print a SHA-256 hash of the request to stderr, which stays the same for identical requests.
+    /// Date, Connection, Content-Length and User-Agent headers are not included.
+    #[structopt(long)]
+    pub fingerprint: bool,
+
+    /// Print how long it took until the response headers arrived and until the body was read.
+    /// Includes redirects and retries. Also prints the size of the body, before and after
+    /// decompressing it. Not shown with --raw-output.
+    #[structopt(long)]
+    pub meta: bool,
+
+    /// Keep requesting the rel="next" URL from the Link header of each response.
+    #[structopt(long = "follow-pagination")]
+    pub follow_pagination: bool,
+
+    /// Stop --follow-pagination after this many pages.
+    #[structopt(long = "max-pages", value_name = "N", requires = "follow-pagination")]
+    pub max_pages: Option<usize>,
+
+    /// Print the JSON arrays of all pages as one array instead of page by page.
+    #[structopt(long = "concat-pages", requires = "follow-pagination")]
+    pub concat_pages: bool,
+
+    /// Save the requests and responses to FILE in the HTTP Archive (HAR) format.
+    #[structopt(long, value_name = "FILE", conflicts_with = "offline")]
+    pub har: Option<String>,
+
+    /// Load cookies and headers from the named session and save them back after the request.
+    /// Named sessions are stored per host in the config directory.
+    #[structopt(long, value_name = "NAME")]
+    pub session: Option<String>,
+
+    /// Like --session, but stored in the given file.
+    #[structopt(long = "session-file", value_name = "FILE", conflicts_with = "session")]
+    pub session_file: Option<String>,
+
+    /// Resend the request up to N times after connection errors, timeouts or a 429, 502, 503 or 504 response.
+    /// With --timeout this can take up to (N + 1) times the timeout, plus the delays between attempts.
+    #[structopt(long, value_name = "N", default_value = "0")]
+    pub retries: usize,
+
+    /// Seconds to wait before the first retry, doubled after every attempt.
+    /// A Retry-After header on a 429 or 503 response takes precedence, up to an hour.
+    #[structopt(long = "retry-delay", value_name = "SECONDS", default_value = "1", parse(try_from_str = parse_seconds))]
+    pub retry_delay: Duration,
+
+    /// Also retry methods that aren't idempotent, such as POST and PATCH.
+    #[structopt(long = "retry-all")]
+    pub retry_all: bool,
+
+    /// Read requests from stdin, one per line, each written as METHOD URL [REQUEST_ITEM]...
+    /// Options given on the command line apply to every request.
+    #[structopt(long)]
+    pub batch: bool,
+
+    /// Stop --batch at the first request that fails.
+    #[structopt(long = "fail-fast", requires = "batch")]
+    pub fail_fast: bool,
+
     /// The default scheme to use if not specified in the URL.
     #[structopt(long = "default-scheme")]
     pub default_scheme: Option<String>,
 
     /// The HTTP method to be used for the request.
-    #[structopt(name = "METHOD", possible_values = &Method::variants(), case_insensitive = true)]
-    pub method: Method,
+    #[structopt(name = "METHOD", possible_values = &Method::variants(), case_insensitive = true, required_unless = "batch")]
+    pub method: Option<Method>,
+
+    #[structopt(name = "URL", required_unless = "batch")]
+    pub url: Option<String>,
 
-    #[structopt(name = "URL")]
-    pub url: String,
+    /// Extra header to send, written like a header request item: -H Name:value or -H Name:@file.
+    /// -d is left unassigned since curl uses it for --data but HTTPie for --download,
+    /// and -o is kept for an --output option.
+    #[structopt(short = "H", long = "header", value_name = "HEADER", number_of_values = 1, parse(try_from_str = parse_header))]
+    pub headers: Vec<RequestItem>,
 
     /// Optional key-value pairs to be included in the request.
+    /// ${VAR} and ${VAR:-default} in them are replaced with environment variables, $${ is a literal ${.
     #[structopt(name = "REQUEST_ITEM")]
     pub request_items: Vec<RequestItem>,
 }
 
+impl Opt {
+    // The option that reads the request body more than once, if any. A --body
+    // file is buffered for these instead of being streamed.
+    pub fn replay_option(&self) -> Option<&'static str> {
+        if self.aws_sigv4.is_some() {
+            Some("--aws-sigv4")
+        } else if self.fingerprint {
+            Some("--fingerprint")
+        } else if self.diff_request.is_some() {
+            Some("--diff-request")
+        } else if self.retries > 0 {
+            Some("--retries")
+        } else if matches!(self.auth_type, Some(AuthType::Digest)) {
+            Some("--auth-type digest")
+        } else if self.redirect_policy == Some(RedirectPolicy::Preserve) {
+            Some("--redirect-policy preserve")
+        } else {
+            None
+        }
+    }
+
+    // Conflicts clap can't express since they depend on option values
+    pub fn validate(&self) -> Result<()> {
+        match (self.body.as_deref(), self.replay_option()) {
+            (Some("@-"), Some(option)) => Err(Error::with_description(
+                &format!(
+                    "--body @- streams stdin, which can't be read again for {}",
+                    option
+                ),
+                ErrorKind::ArgumentConflict,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Headers given with -H go before the request items
+    pub fn all_request_items(&self) -> Vec<RequestItem> {
+        self.headers
+            .iter()
+            .chain(&self.request_items)
+            .cloned()
+            .collect()
+    }
+
+    // The option that reads its value from stdin with @-, if any
+    pub fn stdin_option(&self) -> Option<&'static str> {
+        if self.body.as_deref() == Some("@-") {
+            Some("--body")
+        } else if self.graphql.as_deref() == Some("@-") {
+            Some("--graphql")
+        } else {
+            None
+        }
+    }
+}
+
 // TODO: add remaining methods
 arg_enum! {
     #[derive(Debug, Clone, Copy)]
@@ -64,7 +393,8 @@ arg_enum! {
         POST,
         PUT,
         PATCH,
-        DELETE
+        DELETE,
+        HEAD
     }
 }
 
@@ -76,6 +406,7 @@ impl From<Method> for reqwest::Method {
             Method::PUT => reqwest::Method::PUT,
             Method::PATCH => reqwest::Method::PATCH,
             Method::DELETE => reqwest::Method::DELETE,
+            Method::HEAD => reqwest::Method::HEAD,
         }
     }
 }
@@ -83,7 +414,7 @@ impl From<Method> for reqwest::Method {
 arg_enum! {
     #[derive(Debug)]
     pub enum AuthType {
-        Basic, Bearer
+        Basic, Bearer, Digest
     }
 }
 
@@ -102,30 +433,117 @@ arg_enum! {
     }
 }
 
+// Not an arg_enum since its variants are spelled with dashes on the command line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectPolicy {
+    /// Switch to GET for 301, 302 and 303 but keep the method and body for 307 and 308
+    Standard,
+    /// Switch to GET and drop the body on every redirect
+    AllGet,
+    /// Keep the method and body on every redirect
+    Preserve,
+}
+
+impl RedirectPolicy {
+    pub fn variants() -> [&'static str; 3] {
+        ["standard", "all-get", "preserve"]
+    }
+}
+
+impl FromStr for RedirectPolicy {
+    type Err = String;
+    fn from_str(policy: &str) -> std::result::Result<RedirectPolicy, String> {
+        match policy.to_lowercase().as_str() {
+            "standard" => Ok(RedirectPolicy::Standard),
+            "all-get" => Ok(RedirectPolicy::AllGet),
+            "preserve" => Ok(RedirectPolicy::Preserve),
+            _ => Err(format!("{:?} is not a valid redirect policy", policy)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+    Tls13,
+}
+
+impl FromStr for TlsVersion {
+    type Err = String;
+    fn from_str(version: &str) -> std::result::Result<TlsVersion, String> {
+        match version.to_lowercase().as_str() {
+            "tls1.0" => Ok(TlsVersion::Tls10),
+            "tls1.1" => Ok(TlsVersion::Tls11),
+            "tls1.2" => Ok(TlsVersion::Tls12),
+            "tls1.3" => Ok(TlsVersion::Tls13),
+            _ => Err(format!(
+                "{:?} is not a valid TLS version, expected tls1.0, tls1.1, tls1.2 or tls1.3",
+                version
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wrap {
+    None,
+    Auto,
+    Width(usize),
+}
+
+impl FromStr for Wrap {
+    type Err = String;
+    fn from_str(wrap: &str) -> std::result::Result<Wrap, String> {
+        match wrap.to_lowercase().as_str() {
+            "none" => Ok(Wrap::None),
+            "auto" => Ok(Wrap::Auto),
+            width => match width.parse() {
+                // Continuation lines start with WRAP_INDENT and need room after it
+                Ok(width) if width > WRAP_INDENT.len() => Ok(Wrap::Width(width)),
+                _ => Err(format!(
+                    "{:?} is not a valid wrap, expected none, auto or more than {} columns",
+                    wrap,
+                    WRAP_INDENT.len()
+                )),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum RequestItem {
     HttpHeader(String, String),
+    HttpHeaderFile(String, String),
     UrlParam(String, String),
     DataField(String, String),
-    JSONField(String, serde_json::Value),
+    DataFile(String, String),
+    JSONField(String, String),
+    JSONFile(String, String),
     FormFile(String, String),
 }
 
 impl FromStr for RequestItem {
     type Err = Error;
     fn from_str(request_item: &str) -> Result<RequestItem> {
-        let re = Regex::new(r"^(.+?)(==|:=|=|@|:)(.+)$").unwrap();
+        let re = Regex::new(r"^(.+?)(==|:=@|:=|:@|=@|=|@|:)(.+)$").unwrap();
         if let Some(caps) = re.captures(request_item) {
-            let key = caps[1].to_string();
-            let value = caps[3].to_string();
+            // Expanded after splitting, so a variable's value can't change the separator
+            let expand = |text: &str| {
+                template::expand_env(text)
+                    .map_err(|err| Error::with_description(&err, ErrorKind::InvalidValue))
+            };
+            let key = expand(&caps[1])?;
+            let value = expand(&caps[3])?;
             match &caps[2] {
                 ":" => Ok(RequestItem::HttpHeader(key, value)),
+                ":@" => Ok(RequestItem::HttpHeaderFile(key, value)),
                 "==" => Ok(RequestItem::UrlParam(key, value)),
                 "=" => Ok(RequestItem::DataField(key, value)),
-                ":=" => Ok(RequestItem::JSONField(
-                    key,
-                    serde_json::from_str(&value).unwrap(),
-                )),
+                "=@" => Ok(RequestItem::DataFile(key, value)),
+                ":=" => Ok(RequestItem::JSONField(key, value)),
+                ":=@" => Ok(RequestItem::JSONFile(key, value)),
                 "@" => Ok(RequestItem::FormFile(key, value)),
                 _ => unreachable!(),
             }
@@ -137,3 +555,179 @@ impl FromStr for RequestItem {
         }
     }
 }
+
+fn parse_header(header: &str) -> std::result::Result<RequestItem, String> {
+    match header.parse() {
+        Ok(item @ RequestItem::HttpHeader(..)) | Ok(item @ RequestItem::HttpHeaderFile(..)) => {
+            Ok(item)
+        }
+        _ => Err(format!("{:?} is not a header, expected Name:value", header)),
+    }
+}
+
+// HTTP/2 caps flow control windows at 2^31-1 bytes, see RFC 7540 section 6.9.1
+fn parse_window_size(size: &str) -> std::result::Result<u32, String> {
+    match size.parse::<u32>() {
+        Ok(size) if size > 0 && size < (1 << 31) => Ok(size),
+        _ => Err(format!(
+            "{:?} is not a valid window size, expected 1 to 2147483647",
+            size
+        )),
+    }
+}
+
+// Capped at a year, larger values would overflow Duration or Instant
+const MAX_SECONDS: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+fn parse_seconds(seconds: &str) -> std::result::Result<Duration, String> {
+    match seconds.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds <= MAX_SECONDS => {
+            Ok(Duration::from_secs_f64(seconds))
+        }
+        _ => Err(format!(
+            "{:?} is not a valid number of seconds, expected more than 0 and at most {}",
+            seconds, MAX_SECONDS
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Opt {
+        Opt::from_iter_safe(Some("yahc").iter().chain(args)).unwrap()
+    }
+
+    #[test]
+    fn stdin_option() {
+        let opt = parse(&["post", "http://localhost", "--body", "@-"]);
+        assert_eq!(opt.stdin_option(), Some("--body"));
+        let opt = parse(&["post", "http://localhost", "--graphql", "@-"]);
+        assert_eq!(opt.stdin_option(), Some("--graphql"));
+        let opt = parse(&["post", "http://localhost", "--body", "@data.json"]);
+        assert_eq!(opt.stdin_option(), None);
+    }
+
+    #[test]
+    fn json_conflicts_with_form() {
+        assert!(parse(&["-j", "post", "http://localhost"]).json);
+        let args = ["yahc", "-j", "-f", "post", "http://localhost"];
+        assert!(Opt::from_iter_safe(&args).is_err());
+    }
+
+    #[test]
+    fn aws_sigv4_conflicts_with_other_auth() {
+        let sigv4 = ["yahc", "--aws-sigv4", "s3:us-east-1"];
+        let others: [&[&str]; 3] = [
+            &["-a", "user:pass"],
+            &["--auth-stdin"],
+            &["--bearer-command", "echo token"],
+        ];
+        for other in &others {
+            let args = [&sigv4[..], other, &["get", "http://localhost"]].concat();
+            let err = Opt::from_iter_safe(&args).err().unwrap();
+            assert_eq!(
+                err.kind,
+                structopt::clap::ErrorKind::ArgumentConflict,
+                "{:?}",
+                other
+            );
+        }
+        let args = [&sigv4[..], &["get", "http://localhost"]].concat();
+        assert!(Opt::from_iter_safe(&args).is_ok());
+    }
+
+    #[test]
+    fn short_aliases() {
+        let url = "http://localhost";
+        assert!(parse(&["-k", "get", url]).insecure);
+        assert!(parse(&["--insecure", "get", url]).insecure);
+        assert!(parse(&["-f", "post", url]).form);
+        assert!(parse(&["-j", "post", url]).json);
+
+        let opt = parse(&["-H", "X-A:1", "--header", "X-B:@b.txt", "get", url, "c=1"]);
+        assert_eq!(
+            format!("{:?}", opt.all_request_items()),
+            r#"[HttpHeader("X-A", "1"), HttpHeaderFile("X-B", "b.txt"), DataField("c", "1")]"#
+        );
+        assert!(Opt::from_iter_safe(&["yahc", "-H", "a=1", "get", url]).is_err());
+
+        // -d means --data in curl but --download in HTTPie, -o is kept for --output
+        assert!(Opt::from_iter_safe(&["yahc", "-d", "get", url]).is_err());
+        assert!(Opt::from_iter_safe(&["yahc", "-o", "out", "get", url]).is_err());
+    }
+
+    #[test]
+    fn follow_and_no_follow() {
+        let url = "http://localhost";
+        let follows = |args: &[&str]| {
+            let opt = parse(&[args, &["get", url]].concat());
+            opt.follow || !opt.no_follow
+        };
+        assert!(follows(&[]));
+        assert!(follows(&["-L"]));
+        assert!(!follows(&["--no-follow"]));
+        assert!(follows(&["--no-follow", "--follow"]));
+        assert!(!follows(&["-L", "--no-follow"]));
+    }
+
+    #[test]
+    fn stdin_body_with_replay_options() {
+        let replayed = [
+            (&["--aws-sigv4", "s3:us-east-1"][..], "--aws-sigv4"),
+            (&["--fingerprint"][..], "--fingerprint"),
+            (&["--diff-request", "old.json"][..], "--diff-request"),
+            (&["--retries", "2"][..], "--retries"),
+            (
+                &["-A", "digest", "-a", "user:pass"][..],
+                "--auth-type digest",
+            ),
+            (
+                &["--redirect-policy", "preserve"][..],
+                "--redirect-policy preserve",
+            ),
+        ];
+        for (args, option) in &replayed {
+            let opt = parse(&[*args, &["post", "http://localhost", "--body", "@-"]].concat());
+            assert_eq!(opt.replay_option(), Some(*option));
+            assert!(opt.validate().is_err(), "{}", option);
+
+            let opt = parse(&[*args, &["post", "http://localhost", "--body", "@a.bin"]].concat());
+            assert!(opt.validate().is_ok(), "{}", option);
+        }
+
+        let opt = parse(&["post", "http://localhost", "--body", "@-"]);
+        assert_eq!(opt.replay_option(), None);
+        assert!(opt.validate().is_ok());
+    }
+
+    #[test]
+    fn wrap_widths() {
+        assert_eq!("auto".parse(), Ok(Wrap::Auto));
+        assert_eq!("None".parse(), Ok(Wrap::None));
+        assert_eq!("5".parse(), Ok(Wrap::Width(5)));
+        for wrap in &["0", "4", "-1", "wide"] {
+            assert!(wrap.parse::<Wrap>().is_err(), "{}", wrap);
+        }
+    }
+
+    #[test]
+    fn parse_seconds_bounds() {
+        assert_eq!(parse_seconds("1.5"), Ok(Duration::from_millis(1500)));
+        assert!(parse_seconds("31536000").is_ok());
+        for seconds in &["0", "-1", "NaN", "inf", "1e19", "1e300", "abc"] {
+            assert!(parse_seconds(seconds).is_err(), "{}", seconds);
+        }
+    }
+
+    #[test]
+    fn parse_window_size_bounds() {
+        assert_eq!(parse_window_size("1"), Ok(1));
+        assert_eq!(parse_window_size("65535"), Ok(65535));
+        assert_eq!(parse_window_size("2147483647"), Ok(2147483647));
+        for size in &["0", "2147483648", "4294967296", "-1", "abc"] {
+            assert!(parse_window_size(size).is_err(), "{}", size);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use chrono::{DateTime, Utc};
+
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    pub expires: Option<String>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<String>,
+}
+
+impl Cookie {
+    // https://tools.ietf.org/html/rfc6265#section-5.2
+    pub fn parse(set_cookie: &str) -> Option<Cookie> {
+        let mut parts = set_cookie.split(';');
+        let mut pair = parts.next()?.splitn(2, '=');
+        let name = pair.next()?.trim();
+        let value = pair.next()?.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: None,
+            path: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        };
+        for attribute in parts {
+            let mut attribute = attribute.splitn(2, '=');
+            let key = attribute.next().unwrap_or("").trim().to_lowercase();
+            let value = attribute.next().map(|value| value.trim().to_string());
+            match key.as_str() {
+                "domain" => cookie.domain = value,
+                "path" => cookie.path = value,
+                // Max-Age wins over Expires when both are set
+                "max-age" => cookie.expires = value.map(|age| format!("in {}s", age)),
+                "expires" if cookie.expires.is_none() => cookie.expires = value,
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => cookie.same_site = value,
+                _ => {}
+            }
+        }
+        Some(cookie)
+    }
+
+    pub fn flags(&self) -> String {
+        let mut flags = vec![];
+        if self.secure {
+            flags.push("Secure".to_string());
+        }
+        if self.http_only {
+            flags.push("HttpOnly".to_string());
+        }
+        if let Some(same_site) = &self.same_site {
+            flags.push(format!("SameSite={}", same_site));
+        }
+        flags.join(" ")
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match &self.expires {
+            // Max-Age, see Cookie::parse
+            Some(expires) if expires.starts_with("in ") => {
+                matches!(expires[3..].trim_end_matches('s').parse::<i64>(), Ok(age) if age <= 0)
+            }
+            Some(expires) => matches!(
+                DateTime::parse_from_rfc2822(expires),
+                Ok(expires) if expires < Utc::now()
+            ),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_attributes() {
+        let cookie = Cookie::parse(
+            "sid=abc=def; Domain=example.com; Path=/; Secure; HttpOnly; SameSite=Lax",
+        )
+        .unwrap();
+        assert_eq!(cookie.name, "sid");
+        assert_eq!(cookie.value, "abc=def");
+        assert_eq!(cookie.domain.as_deref(), Some("example.com"));
+        assert_eq!(cookie.path.as_deref(), Some("/"));
+        assert_eq!(cookie.flags(), "Secure HttpOnly SameSite=Lax");
+    }
+
+    #[test]
+    fn max_age_wins_over_expires() {
+        let expires = "Expires=Wed, 21 Oct 2015 07:28:00 GMT";
+        let cookie = Cookie::parse(&format!("a=1; Max-Age=0; {}", expires)).unwrap();
+        assert_eq!(cookie.expires.as_deref(), Some("in 0s"));
+        assert!(cookie.is_expired());
+        let cookie = Cookie::parse(&format!("a=1; {}", expires)).unwrap();
+        assert!(cookie.is_expired());
+        assert!(!Cookie::parse("a=1; Max-Age=60").unwrap().is_expired());
+        assert!(!Cookie::parse("a=1").unwrap().is_expired());
+    }
+
+    #[test]
+    fn invalid_cookies() {
+        assert!(Cookie::parse("novalue").is_none());
+        assert!(Cookie::parse("=value").is_none());
+    }
+}
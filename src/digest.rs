@@ -0,0 +1,322 @@
+use std::error::Error;
+
+use md5::Md5;
+use rand::Rng;
+use reqwest::blocking::{Client, Request, Response};
+use reqwest::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+
+use crate::redirect::{self, Hops};
+use crate::utils::hex;
+use crate::RedirectPolicy;
+
+struct Challenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: String,
+    qop: Option<String>,
+    stale: bool,
+}
+
+// Answers a Digest challenge by resending the request, see RFC 7616. A second
+// attempt is only made when the server says the nonce used by the first was stale.
+pub fn execute(
+    client: &Client,
+    request: Request,
+    policy: RedirectPolicy,
+    max_redirects: Option<usize>,
+    hops: &mut Hops,
+    username: &str,
+    password: &str,
+) -> Result<Response, Box<dyn Error>> {
+    let origin = request.url().clone();
+    let mut response = redirect::execute(client, request, policy, max_redirects, hops)?;
+
+    // The challenge is answered with the request that got it, which after a 303
+    // may have another method and no body
+    let retry = match hops.last_request.take() {
+        Some(retry) => retry,
+        None => return Ok(response),
+    };
+    for attempt in 0..2 {
+        if response.status() != StatusCode::UNAUTHORIZED {
+            break;
+        }
+        // Credentials are only ever answered to the host they were meant for
+        let url = response.url().clone();
+        if url.host_str() != origin.host_str()
+            || url.port_or_known_default() != origin.port_or_known_default()
+        {
+            break;
+        }
+        let challenge = match find_challenge(&response) {
+            Some(challenge) if attempt == 0 || challenge.stale => challenge,
+            _ => break,
+        };
+
+        let mut request = retry.try_clone().unwrap();
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .unwrap_or(&[])
+            .to_vec();
+        let uri = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let authorization = authorization(
+            &challenge,
+            request.method().as_str(),
+            &uri,
+            &body,
+            username,
+            password,
+        )?;
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, HeaderValue::from_str(&authorization)?);
+        response = redirect::execute(client, request, policy, max_redirects, hops)?;
+    }
+    Ok(response)
+}
+
+fn find_challenge(response: &Response) -> Option<Challenge> {
+    response
+        .headers()
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| {
+            let scheme = value.get(..7)?;
+            if scheme.eq_ignore_ascii_case("digest ") {
+                parse_challenge(&value[7..])
+            } else {
+                None
+            }
+        })
+        .next()
+}
+
+fn parse_challenge(params: &str) -> Option<Challenge> {
+    let mut realm = None;
+    let mut nonce = None;
+    let mut opaque = None;
+    let mut algorithm = "MD5".to_string();
+    let mut qop = None;
+    let mut stale = false;
+
+    for (key, value) in parse_params(params) {
+        match key.to_lowercase().as_str() {
+            "realm" => realm = Some(value),
+            "nonce" => nonce = Some(value),
+            "opaque" => opaque = Some(value),
+            "algorithm" => algorithm = value,
+            "qop" => {
+                let offered: Vec<String> =
+                    value.split(',').map(|q| q.trim().to_lowercase()).collect();
+                qop = if offered.iter().any(|q| q == "auth") {
+                    Some("auth".to_string())
+                } else if offered.iter().any(|q| q == "auth-int") {
+                    Some("auth-int".to_string())
+                } else {
+                    None
+                };
+            }
+            "stale" => stale = value.eq_ignore_ascii_case("true"),
+            _ => {}
+        }
+    }
+
+    Some(Challenge {
+        realm: realm?,
+        nonce: nonce?,
+        opaque,
+        algorithm,
+        qop,
+        stale,
+    })
+}
+
+// key=token or key="quoted string" pairs separated by commas
+fn parse_params(params: &str) -> Vec<(String, String)> {
+    let mut pairs = vec![];
+    let mut chars = params.chars().peekable();
+    loop {
+        while let Some(c) = chars.peek() {
+            if c.is_whitespace() || *c == ',' {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if key.is_empty() {
+            break;
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    _ => value.push(c),
+                }
+            }
+        } else {
+            value = chars.by_ref().take_while(|c| *c != ',').collect();
+        }
+        pairs.push((key.trim().to_string(), value.trim().to_string()));
+    }
+    pairs
+}
+
+fn authorization(
+    challenge: &Challenge,
+    method: &str,
+    uri: &str,
+    body: &[u8],
+    username: &str,
+    password: &str,
+) -> Result<String, String> {
+    let cnonce = format!("{:016x}", rand::thread_rng().gen::<u64>());
+    authorization_with_cnonce(challenge, method, uri, body, username, password, &cnonce)
+}
+
+fn authorization_with_cnonce(
+    challenge: &Challenge,
+    method: &str,
+    uri: &str,
+    body: &[u8],
+    username: &str,
+    password: &str,
+    cnonce: &str,
+) -> Result<String, String> {
+    let (hash, session): (fn(&[u8]) -> String, bool) =
+        match challenge.algorithm.to_uppercase().as_str() {
+            "MD5" => (md5_hex, false),
+            "MD5-SESS" => (md5_hex, true),
+            "SHA-256" => (sha256_hex, false),
+            "SHA-256-SESS" => (sha256_hex, true),
+            algorithm => return Err(format!("Unsupported digest algorithm {}", algorithm)),
+        };
+    let nc = "00000001";
+
+    let mut ha1 = hash(format!("{}:{}:{}", username, challenge.realm, password).as_bytes());
+    if session {
+        ha1 = hash(format!("{}:{}:{}", ha1, challenge.nonce, cnonce).as_bytes());
+    }
+    let ha2 = match challenge.qop.as_deref() {
+        Some("auth-int") => hash(format!("{}:{}:{}", method, uri, hash(body)).as_bytes()),
+        _ => hash(format!("{}:{}", method, uri).as_bytes()),
+    };
+    let response = match &challenge.qop {
+        Some(qop) => hash(
+            format!(
+                "{}:{}:{}:{}:{}:{}",
+                ha1, challenge.nonce, nc, cnonce, qop, ha2
+            )
+            .as_bytes(),
+        ),
+        None => hash(format!("{}:{}:{}", ha1, challenge.nonce, ha2).as_bytes()),
+    };
+
+    let mut header = format!(
+        "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+        username, challenge.realm, challenge.nonce, uri, challenge.algorithm, response
+    );
+    if let Some(qop) = &challenge.qop {
+        header.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop, nc, cnonce));
+    }
+    if let Some(opaque) = &challenge.opaque {
+        header.push_str(&format!(", opaque=\"{}\"", opaque));
+    }
+    Ok(header)
+}
+
+fn md5_hex(data: &[u8]) -> String {
+    hex(&Md5::digest(data))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(header: &str) -> Challenge {
+        parse_challenge(header.strip_prefix("Digest ").unwrap()).unwrap()
+    }
+
+    // RFC 2617 section 3.5
+    #[test]
+    fn rfc_2617_example() {
+        let challenge = challenge(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        );
+        let header = authorization_with_cnonce(
+            &challenge,
+            "GET",
+            "/dir/index.html",
+            b"",
+            "Mufasa",
+            "Circle Of Life",
+            "0a4f113b",
+        )
+        .unwrap();
+        assert_eq!(
+            header,
+            "Digest username=\"Mufasa\", realm=\"testrealm@host.com\", \
+             nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", uri=\"/dir/index.html\", \
+             algorithm=MD5, response=\"6629fae49393a05397450978507c4ef1\", qop=auth, \
+             nc=00000001, cnonce=\"0a4f113b\", opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""
+        );
+    }
+
+    // RFC 7616 section 3.9.1
+    #[test]
+    fn rfc_7616_example() {
+        let header = |algorithm: &str| {
+            let challenge = challenge(&format!(
+                r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm={}, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+                algorithm
+            ));
+            authorization_with_cnonce(
+                &challenge,
+                "GET",
+                "/dir/index.html",
+                b"",
+                "Mufasa",
+                "Circle of Life",
+                "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+            )
+            .unwrap()
+        };
+        assert!(header("MD5").contains("response=\"8ca523f5e9506fed4657c9700eebdbec\""));
+        assert!(header("SHA-256").contains(
+            "response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""
+        ));
+    }
+
+    #[test]
+    fn challenge_parameters() {
+        let challenge =
+            challenge(r#"Digest realm="a \"b\"", nonce=xyz, stale=TRUE, qop="auth-int""#);
+        assert_eq!(challenge.realm, "a \"b\"");
+        assert_eq!(challenge.nonce, "xyz");
+        assert_eq!(challenge.algorithm, "MD5");
+        assert_eq!(challenge.qop.as_deref(), Some("auth-int"));
+        assert!(challenge.stale);
+        assert!(parse_challenge(r#"realm="no nonce""#).is_none());
+    }
+
+    #[test]
+    fn unsupported_algorithm() {
+        let challenge = challenge(r#"Digest realm="r", nonce="n", algorithm=SHA-512-256"#);
+        assert!(authorization(&challenge, "GET", "/", b"", "u", "p").is_err());
+    }
+}
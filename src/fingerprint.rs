@@ -0,0 +1,92 @@
+use reqwest::blocking::Request;
+use reqwest::header::{CONNECTION, CONTENT_LENGTH, DATE, USER_AGENT};
+use sha2::{Digest, Sha256};
+
+use crate::utils::hex;
+
+// SHA-256 of the method, URL, headers sorted by name and the body. Headers that
+// can differ between otherwise identical requests are left out: Date,
+// Connection, Content-Length (the body is hashed anyway) and User-Agent
+// (random with --user-agent-file). Streaming bodies such as multipart
+// uploads can't be read in advance and count as empty.
+pub fn fingerprint(request: &Request) -> String {
+    let mut headers: Vec<(&str, &[u8])> = request
+        .headers()
+        .iter()
+        .filter(|(name, _)| ![DATE, CONNECTION, CONTENT_LENGTH, USER_AGENT].contains(name))
+        .map(|(name, value)| (name.as_str(), value.as_bytes()))
+        .collect();
+    headers.sort();
+
+    let mut hasher = Sha256::new();
+    hasher.update(request.method().as_str());
+    hasher.update(b"\n");
+    hasher.update(request.url().as_str());
+    hasher.update(b"\n");
+    for (name, value) in headers {
+        hasher.update(name);
+        hasher.update(b":");
+        hasher.update(value);
+        hasher.update(b"\n");
+    }
+    hasher.update(b"\n");
+    if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+        hasher.update(body);
+    }
+    hex(&hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use reqwest::Method;
+
+    fn request(headers: &[(&'static str, &'static str)], body: &'static str) -> Request {
+        let url = "http://example.com/items?a=1".parse().unwrap();
+        let mut request = Request::new(Method::POST, url);
+        for (name, value) in headers {
+            let value = HeaderValue::from_static(value);
+            request.headers_mut().append(*name, value);
+        }
+        *request.body_mut() = Some(body.into());
+        request
+    }
+
+    #[test]
+    fn stable_for_identical_requests() {
+        let first = request(&[("accept", "*/*"), ("x-a", "1")], "{}");
+        let second = request(&[("x-a", "1"), ("accept", "*/*")], "{}");
+        assert_eq!(fingerprint(&first), fingerprint(&second));
+        assert_eq!(fingerprint(&first).len(), 64);
+    }
+
+    #[test]
+    fn ignores_volatile_headers() {
+        let plain = request(&[("x-a", "1")], "{}");
+        let volatile = request(
+            &[
+                ("x-a", "1"),
+                ("date", "Wed, 21 Oct 2015 07:28:00 GMT"),
+                ("user-agent", "yahc/1.0"),
+                ("content-length", "2"),
+                ("connection", "keep-alive"),
+            ],
+            "{}",
+        );
+        assert_eq!(fingerprint(&plain), fingerprint(&volatile));
+    }
+
+    #[test]
+    fn differs_by_header_and_body() {
+        let plain = request(&[("x-a", "1")], "{}");
+        assert_ne!(
+            fingerprint(&plain),
+            fingerprint(&request(&[("x-a", "2")], "{}"))
+        );
+        assert_ne!(
+            fingerprint(&plain),
+            fingerprint(&request(&[("x-a", "1")], "[]"))
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::error::Error;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use chrono::{SecondsFormat, Utc};
+use reqwest::blocking::{Request, Response};
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use serde_json::{json, Value};
+
+use crate::utils::{decode_body, read_raw_body};
+
+// Collects one entry per response for an HTTP Archive 1.2 file, see
+// http://www.softwareishard.com/blog/har-12-spec/
+pub struct Har {
+    entries: Vec<Value>,
+    stall_timeout: Option<Duration>,
+}
+
+// The request as it was just before sending, which consumes it
+pub struct Entry {
+    started_date_time: String,
+    started: Instant,
+    request: Value,
+}
+
+impl Har {
+    pub fn new(stall_timeout: Option<Duration>) -> Har {
+        Har {
+            entries: vec![],
+            stall_timeout,
+        }
+    }
+
+    pub fn start(&self, request: &Request) -> Entry {
+        let url = request.url();
+        let query_string: Vec<Value> = url
+            .query_pairs()
+            .map(|(name, value)| json!({ "name": name, "value": value }))
+            .collect();
+        let mut har_request = json!({
+            "method": request.method().as_str(),
+            "url": url.as_str(),
+            "httpVersion": "HTTP/1.1",
+            "cookies": [],
+            "headers": headers(request.headers()),
+            "queryString": query_string,
+            "headersSize": -1,
+            "bodySize": -1,
+        });
+        // Multipart bodies are streamed and can't be read in advance
+        if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+            let mut post_data = json!({ "mimeType": mime_type(request.headers()) });
+            add_text(&mut post_data, body);
+            har_request["postData"] = post_data;
+            har_request["bodySize"] = json!(body.len());
+        }
+        Entry {
+            started_date_time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            started: Instant::now(),
+            request: har_request,
+        }
+    }
+
+    // Reads the whole body and hands back a response with the same status,
+    // headers and body, so that it can still be printed afterwards
+    pub fn finish(
+        &mut self,
+        entry: Entry,
+        response: Response,
+        deadline: Option<Instant>,
+    ) -> Result<Response, Box<dyn Error>> {
+        let wait = entry.started.elapsed();
+        let status = response.status();
+        let version = response.version();
+        let response_headers = response.headers().clone();
+        let body = read_raw_body(response, self.stall_timeout, deadline)?;
+        let receive = entry.started.elapsed() - wait;
+
+        let decoded = decode_body(&response_headers, body.clone())?;
+        let mut content = json!({
+            "size": decoded.len(),
+            "mimeType": mime_type(&response_headers),
+        });
+        add_text(&mut content, &decoded);
+
+        let mut request = entry.request;
+        request["httpVersion"] = json!(format!("{:?}", version));
+        self.entries.push(json!({
+            "startedDateTime": entry.started_date_time,
+            "time": millis(wait + receive),
+            "request": request,
+            "response": {
+                "status": status.as_u16(),
+                "statusText": status.canonical_reason().unwrap_or(""),
+                "httpVersion": format!("{:?}", version),
+                "cookies": [],
+                "headers": headers(&response_headers),
+                "content": content,
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": body.len(),
+            },
+            "cache": {},
+            "timings": {
+                "send": 0,
+                "wait": millis(wait),
+                "receive": millis(receive),
+            },
+        }));
+
+        let mut rebuilt = http::Response::builder().status(status).version(version);
+        if let Some(rebuilt_headers) = rebuilt.headers_mut() {
+            *rebuilt_headers = response_headers;
+        }
+        Ok(rebuilt.body(body)?.into())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let har = json!({
+            "log": {
+                "version": "1.2",
+                "creator": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "entries": self.entries,
+            }
+        });
+        fs::write(path, serde_json::to_string_pretty(&har).unwrap())
+            .map_err(|err| format!("Failed to write {}: {}", path, err))
+    }
+}
+
+fn headers(headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            json!({
+                "name": name.as_str(),
+                "value": String::from_utf8_lossy(value.as_bytes()),
+            })
+        })
+        .collect()
+}
+
+fn mime_type(headers: &HeaderMap) -> &str {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+}
+
+// Bodies that aren't valid UTF-8 are stored as base64
+fn add_text(target: &mut Value, body: &[u8]) {
+    match std::str::from_utf8(body) {
+        Ok(text) => target["text"] = json!(text),
+        Err(_) => {
+            target["text"] = json!(base64::encode(body));
+            target["encoding"] = json!("base64");
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -1,65 +1,616 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead};
+use std::process;
+use std::time::{Duration, Instant};
+
+use native_tls::{Protocol, TlsConnector};
 use reqwest::blocking::Client;
+use reqwest::header::{HeaderValue, CONTENT_TYPE, USER_AGENT};
+use reqwest::redirect::Policy;
 use structopt::StructOpt;
 #[macro_use]
 extern crate lazy_static;
 
 mod auth;
 mod cli;
+mod cookies;
+mod diff;
+mod digest;
+mod fingerprint;
+mod har;
+mod pagination;
 mod printer;
+mod redirect;
 mod request_items;
+mod retry;
+mod session;
+mod signing;
+mod template;
 mod url;
 mod utils;
 
-use auth::Auth;
-use cli::{AuthType, Opt, Pretty, RequestItem, Theme};
+use auth::{read_auth_from_stdin, run_bearer_command, Auth};
+use cli::{AuthType, Opt, Pretty, RedirectPolicy, RequestItem, Theme, TlsVersion, Wrap};
+use har::Har;
 use printer::Printer;
+use redirect::Hops;
 use request_items::{Body, RequestItems};
+use retry::Retries;
+use session::Session;
+use signing::{sign_aws_sigv4, AwsCredentials};
 use url::Url;
+use utils::random_user_agent;
 
-fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+fn main() {
     let opt = Opt::from_args();
+    if let Err(err) = opt.validate() {
+        err.exit();
+    }
+    let code = if opt.batch {
+        run_batch(opt.fail_fast)
+    } else {
+        run(opt)
+    };
+    process::exit(code);
+}
+
+fn run(opt: Opt) -> i32 {
+    let timeout = opt.timeout;
+    match inner_main(opt) {
+        Ok(()) => 0,
+        Err(err) => {
+            // With --timeout set the client's own timeout is the total deadline
+            let err = match (err.downcast_ref::<Timeout>(), timeout) {
+                (Some(Timeout::Read), Some(timeout)) => Box::new(Timeout::Total(timeout)),
+                _ => err,
+            };
+            eprintln!("Error: {:?}", err);
+            match err.downcast_ref::<Timeout>() {
+                Some(Timeout::Read) => 2,
+                Some(Timeout::Connect) => 3,
+                Some(Timeout::Total(_)) => 4,
+                None => 1,
+            }
+        }
+    }
+}
+
+// Every line is parsed as if it was appended to the options yahc was started with
+fn run_batch(fail_fast: bool) -> i32 {
+    let options: Vec<String> = env::args()
+        .filter(|arg| arg != "--batch" && arg != "--fail-fast")
+        .collect();
+    let mut code = 0;
+    for (number, line) in io::stdin().lock().lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("Error: {:?}", err.to_string());
+                return 1;
+            }
+        };
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        println!("[{}] {}", number + 1, line.trim());
+        let line_code = match shell_words::split(&line) {
+            Ok(words) => match Opt::from_iter_safe(options.iter().cloned().chain(words)) {
+                Ok(opt) if opt.batch => {
+                    eprintln!("Error: \"--batch can't be used inside a batch\"");
+                    1
+                }
+                // The batch itself is read from stdin, which stays locked
+                Ok(opt) => match opt.stdin_option() {
+                    Some(option) => {
+                        eprintln!(
+                            "Error: \"{} @- can't be used inside a batch, stdin holds the batch\"",
+                            option
+                        );
+                        1
+                    }
+                    None => run(opt),
+                },
+                Err(err) => {
+                    eprintln!("{}", err.message);
+                    1
+                }
+            },
+            Err(err) => {
+                eprintln!("Error: {:?}", err.to_string());
+                1
+            }
+        };
+        if line_code != 0 {
+            if code == 0 {
+                code = line_code;
+            }
+            if fail_fast {
+                break;
+            }
+        }
+    }
+    code
+}
+
+fn inner_main(opt: Opt) -> std::result::Result<(), Box<dyn Error>> {
+    if opt.dry_run {
+        return dry_run(&opt);
+    }
 
     let printer = Printer::new(&opt);
-    let request_items = RequestItems::new(opt.request_items);
+    let request_items = RequestItems::new(opt.all_request_items());
+    // A streamed file can't be shown, so read it up front when -v should dump it
+    let buffer_body = opt.replay_option().is_some() || (opt.verbose && opt.hexdump);
 
-    let url = Url::new(opt.url, opt.default_scheme);
-    let method = opt.method.into();
-    let auth = Auth::new(opt.auth, opt.auth_type, &url);
+    // clap only lets METHOD and URL be missing together with --batch
+    let url = Url::new(opt.url.unwrap(), opt.default_scheme)?;
+    let method = opt.method.unwrap().into();
+    let auth = if opt.auth_stdin {
+        Some(read_auth_from_stdin()?)
+    } else {
+        opt.auth
+    };
+    let auth = match &opt.bearer_command {
+        Some(command) => Some(Auth::Bearer(run_bearer_command(command)?)),
+        None => Auth::new(auth, opt.auth_type, &url)?,
+    };
     let query = request_items.query();
-    let headers = request_items.headers(&url);
-    let body = request_items.body(opt.form)?;
+    let mut headers = request_items.headers(&url)?;
+    let mut session = load_session(&opt.session, &opt.session_file, &url)?;
+    if let Some(session) = &session {
+        session.apply(
+            &mut headers,
+            &request_items.header_names(),
+            &url.0,
+            auth.is_some(),
+        )?;
+    }
+    // clap makes sure that at most one of these is given
+    let file_body = if let Some(body) = &opt.body {
+        Some(Body::from_file(body, opt.as_json, buffer_body)?)
+    } else if let Some(template) = &opt.body_template {
+        Some(Body::from_template(
+            template,
+            opt.data_file.as_deref(),
+            opt.allow_missing,
+        )?)
+    } else if let Some(prefix) = &opt.body_env {
+        Some(Body::from_env(prefix))
+    } else {
+        opt.raw.as_ref().map(|raw| {
+            Body::Raw(
+                raw.clone().into_bytes(),
+                "text/plain; charset=utf-8".to_string(),
+            )
+        })
+    };
+    let body = request_items.body(opt.form, opt.json_lenient, opt.allow_exec)?;
+    let mut body = if let Some(query) = &opt.graphql {
+        Some(Body::from_graphql(query, body)?)
+    } else {
+        match (body, file_body) {
+            (Some(_), Some(_)) => return Err(
+                "Request body items cannot be combined with --body, --body-template, --body-env or --raw"
+                    .into(),
+            ),
+            (body, None) | (None, body) => body,
+        }
+    };
+
+    let json_items = match &body {
+        Some(Body::Json(_)) if opt.graphql.is_none() => request_items.data_items(),
+        _ => 0,
+    };
+
+    if let Some(path) = &opt.user_agent_file {
+        let user_agent = HeaderValue::from_str(&random_user_agent(path)?)?;
+        headers.entry(USER_AGENT).or_insert(user_agent);
+    }
+
+    if let Some(Body::Raw(_, content_type)) | Some(Body::Stream(_, content_type)) = &mut body {
+        if opt.json {
+            *content_type = "application/json".to_string();
+        }
+        let content_type = HeaderValue::from_str(content_type)?;
+        headers.entry(CONTENT_TYPE).or_insert(content_type);
+    }
 
-    let client = Client::new();
-    let request = {
+    if opt.check_revocation {
+        return Err("--check-revocation is not supported by the TLS backend (native-tls)".into());
+    }
+
+    let redirect_policy = opt.redirect_policy.unwrap_or(RedirectPolicy::Standard);
+    // The last of --follow and --no-follow wins
+    let follow = opt.follow || !opt.no_follow;
+    let mut client_builder = Client::builder()
+        .redirect(Policy::none())
+        .danger_accept_invalid_certs(opt.insecure);
+    if let Some(timeout) = opt.connect_timeout {
+        client_builder = client_builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = opt.pool_idle_timeout {
+        client_builder = client_builder.pool_idle_timeout(timeout);
+    }
+    if let Some(timeout) = opt.timeout {
+        client_builder = client_builder.timeout(timeout);
+    }
+    if let Some(max) = opt.pool_max_idle {
+        client_builder = client_builder.pool_max_idle_per_host(max);
+    }
+    if let Some(size) = opt.http2_initial_window_size {
+        client_builder = client_builder.http2_initial_stream_window_size(size);
+    }
+    if opt.tls_min.is_some() || opt.tls_max.is_some() {
+        let connector = tls_connector(opt.tls_min, opt.tls_max, opt.insecure)?;
+        client_builder = client_builder.use_preconfigured_tls(connector);
+    }
+    if let Some(size) = opt.http2_initial_connection_window_size {
+        client_builder = client_builder.http2_initial_connection_window_size(size);
+    }
+    let client = client_builder.build()?;
+    let mut request = {
         let mut request_builder = client.request(method, url.0).query(&query).headers(headers);
 
         request_builder = match body {
             Some(Body::Json(body)) => request_builder.json(&body),
             Some(Body::Form(body)) => request_builder.form(&body),
             Some(Body::Multipart(body)) => request_builder.multipart(body),
+            Some(Body::Raw(body, _)) => request_builder.body(body),
+            Some(Body::Stream(body, _)) => request_builder.body(body),
             None => request_builder,
         };
 
-        request_builder = match auth {
+        request_builder = match &auth {
             Some(Auth::Bearer(token)) => request_builder.bearer_auth(token),
-            Some(Auth::Basic(username, password)) => request_builder.basic_auth(username, password),
-            None => request_builder,
+            Some(Auth::Basic(username, password)) => {
+                request_builder.basic_auth(username, password.as_ref())
+            }
+            // Digest credentials can only be sent once the server has sent a challenge
+            Some(Auth::Digest(_, _)) | None => request_builder,
         };
 
         request_builder.build()?
     };
 
-    print!("\n");
+    // Before signing, which adds a timestamp
+    if opt.fingerprint {
+        eprintln!("Fingerprint: {}", fingerprint::fingerprint(&request));
+    }
+
+    if let Some(service_region) = &opt.aws_sigv4 {
+        let credentials = AwsCredentials::new(
+            opt.aws_access_key_id,
+            opt.aws_secret_access_key,
+            opt.aws_session_token,
+        )?;
+        sign_aws_sigv4(&mut request, service_region, &credentials)?;
+    }
+
+    if !opt.raw_output && opt.print_header.is_empty() {
+        println!();
+    }
+
+    if let Some(baseline) = &opt.diff_request {
+        let body = request.body().and_then(|body| body.as_bytes());
+        let body: serde_json::Value = match body.map(serde_json::from_slice) {
+            Some(Ok(body)) => body,
+            _ => return Err("--diff-request needs a JSON request body".into()),
+        };
+        let baseline: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(baseline)
+                .map_err(|err| format!("Failed to read {}: {}", baseline, err))?,
+        )
+        .map_err(|err| format!("{} is not valid JSON: {}", baseline, err))?;
+
+        if body != baseline {
+            let baseline = serde_json::to_string_pretty(&baseline)?;
+            let body = serde_json::to_string_pretty(&body)?;
+            printer.print_diff(&diff::diff_lines(&baseline, &body));
+            return Err("The request body differs from the --diff-request baseline".into());
+        }
+    }
 
     if opt.verbose {
+        printer.print_request_query(&request);
         printer.print_request_headers(&request);
+        if json_items > 0 {
+            printer.print_json_note(json_items);
+        }
         printer.print_request_body(&request);
     }
 
     if !opt.offline {
-        let response = client.execute(request)?;
-        printer.print_response_headers(&response);
-        printer.print_response_body(response);
+        let method = request.method().clone();
+        let sent_headers = request.headers().clone();
+        let retries = Retries {
+            count: opt.retries,
+            delay: opt.retry_delay,
+            all_methods: opt.retry_all,
+        };
+        let max_redirects = Some(opt.max_redirects).filter(|_| follow);
+        let send = |request, hops: &mut Hops| match &auth {
+            Some(Auth::Digest(username, password)) => digest::execute(
+                &client,
+                request,
+                redirect_policy,
+                max_redirects,
+                hops,
+                username,
+                password,
+            ),
+            _ => redirect::execute(&client, request, redirect_policy, max_redirects, hops),
+        };
+
+        let mut har = if opt.har.is_some() {
+            Some(Har::new(opt.stall_timeout))
+        } else {
+            None
+        };
+        let mut visited = vec![request.url().clone()];
+        let mut concatenated = vec![];
+        loop {
+            let next_request = if opt.follow_pagination {
+                request.try_clone()
+            } else {
+                None
+            };
+            let har_entry = har.as_ref().map(|har| har.start(&request));
+            let request_url = request.url().clone();
+            let mut hops = Hops::default();
+            let started = Instant::now();
+            let response = retry::execute(request, &retries, |request| send(request, &mut hops))
+                .map_err(explain_error)?;
+            let first_byte = started.elapsed();
+            let deadline = match (opt.timeout, hops.sent) {
+                (Some(timeout), Some(sent)) => sent.checked_add(timeout),
+                _ => None,
+            };
+            if let Some(session) = &mut session {
+                let given = request_items.header_names();
+                session.update(&sent_headers, &given, &request_url, &hops);
+                session.save()?;
+            }
+
+            let more_pages = !matches!(opt.max_pages, Some(max) if visited.len() >= max);
+            // A next link back to an earlier page would never end
+            let next_page =
+                pagination::next_page(&response).filter(|url| more_pages && !visited.contains(url));
+            let page_url = response.url().clone();
+            // Reads the body up front, the response handed back has no URL
+            let response = match (&mut har, har_entry) {
+                (Some(har), Some(entry)) => har
+                    .finish(entry, response, deadline)
+                    .map_err(explain_error)?,
+                _ => response,
+            };
+            let size = if opt.print_header.is_empty() {
+                printer.print_response_headers(&response);
+                if opt.show_cookies {
+                    printer.print_response_cookies(&response);
+                }
+                if opt.concat_pages {
+                    concatenated.extend(
+                        printer
+                            .read_json_page(response, &page_url, deadline)
+                            .map_err(explain_error)?,
+                    );
+                    None
+                } else {
+                    printer
+                        .print_response_body(response, &method, deadline)
+                        .map_err(explain_error)?
+                }
+            } else {
+                printer.print_response_header_values(&response, &opt.print_header);
+                None
+            };
+            if opt.meta {
+                printer.print_meta(first_byte, started.elapsed(), size.as_ref());
+            }
+
+            request = match (next_page, next_request) {
+                (Some(url), Some(mut next_request)) => {
+                    visited.push(url.clone());
+                    redirect::retarget(&mut next_request, url, &page_url)?;
+                    next_request
+                }
+                _ => break,
+            };
+        }
+        if opt.concat_pages && opt.print_header.is_empty() {
+            printer.print_json_value(&serde_json::Value::Array(concatenated));
+        }
+        if let (Some(har), Some(path)) = (&har, &opt.har) {
+            har.save(path)?;
+        }
     }
     Ok(())
 }
+
+// reqwest can only limit TLS versions through a connector built by hand
+fn tls_connector(
+    min: Option<TlsVersion>,
+    max: Option<TlsVersion>,
+    insecure: bool,
+) -> std::result::Result<TlsConnector, String> {
+    if let (Some(min), Some(max)) = (min, max) {
+        if min > max {
+            return Err("--tls-min can't be newer than --tls-max".to_string());
+        }
+    }
+    // native-tls has no way to name TLS 1.3, leaving out the ceiling allows it
+    let protocol = |version| match version {
+        TlsVersion::Tls10 => Some(Protocol::Tlsv10),
+        TlsVersion::Tls11 => Some(Protocol::Tlsv11),
+        TlsVersion::Tls12 => Some(Protocol::Tlsv12),
+        TlsVersion::Tls13 => None,
+    };
+    if min == Some(TlsVersion::Tls13) {
+        return Err(
+            "--tls-min tls1.3 is not supported by the TLS backend (native-tls)".to_string(),
+        );
+    }
+    TlsConnector::builder()
+        .min_protocol_version(min.and_then(protocol))
+        .max_protocol_version(max.and_then(protocol))
+        .danger_accept_invalid_certs(insecure)
+        .build()
+        .map_err(|err| format!("Failed to set up TLS: {}", err))
+}
+
+fn load_session(
+    name: &Option<String>,
+    path: &Option<String>,
+    url: &Url,
+) -> std::result::Result<Option<Session>, String> {
+    match (name, path) {
+        (Some(name), _) => {
+            let host = match url.0.port() {
+                Some(port) => format!("{}:{}", url.host().unwrap(), port),
+                None => url.host().unwrap(),
+            };
+            Session::named(name, &host).map(Some)
+        }
+        (None, Some(path)) => Session::from_file(path).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+// Collects every problem that would stop the request instead of failing at the first one
+fn dry_run(opt: &Opt) -> std::result::Result<(), Box<dyn Error>> {
+    let mut problems = vec![];
+
+    match Url::new(opt.url.clone().unwrap(), opt.default_scheme.clone()) {
+        Ok(url) => {
+            if let Err(err) = load_session(&opt.session, &opt.session_file, &url) {
+                problems.push(err);
+            }
+        }
+        Err(err) => problems.push(err),
+    }
+
+    let request_items = RequestItems::new(opt.all_request_items());
+    let item_problems = request_items.validate(opt.json_lenient, opt.allow_exec);
+    // Building a multipart body with a missing file would fail on that file again
+    if item_problems.is_empty() {
+        if let Err(err) = request_items.body(opt.form, opt.json_lenient, opt.allow_exec) {
+            problems.push(err);
+        }
+    }
+    problems.extend(item_problems);
+
+    if let Some(body) = &opt.body {
+        if let Err(err) = Body::from_file(body, opt.as_json, opt.replay_option().is_some()) {
+            problems.push(err);
+        }
+    }
+    if let Some(template) = &opt.body_template {
+        if let Err(err) = Body::from_template(template, opt.data_file.as_deref(), opt.allow_missing)
+        {
+            problems.push(err);
+        }
+    }
+    if let Some(query) = &opt.graphql {
+        if let Err(err) = Body::from_graphql(query, None) {
+            problems.push(err);
+        }
+    }
+    if let Some(path) = &opt.user_agent_file {
+        if let Err(err) = random_user_agent(path) {
+            problems.push(err);
+        }
+    }
+    if opt.aws_sigv4.is_some() {
+        if let Err(err) = AwsCredentials::new(
+            opt.aws_access_key_id.clone(),
+            opt.aws_secret_access_key.clone(),
+            None,
+        ) {
+            problems.push(err.to_string());
+        }
+    }
+    if opt.tls_min.is_some() || opt.tls_max.is_some() {
+        if let Err(err) = tls_connector(opt.tls_min, opt.tls_max, opt.insecure) {
+            problems.push(err);
+        }
+    }
+    if opt.check_revocation {
+        problems.push("--check-revocation is not supported by the TLS backend (native-tls)".into());
+    }
+
+    if problems.is_empty() {
+        println!("No problems found, the request was not sent");
+        return Ok(());
+    }
+    for problem in &problems {
+        eprintln!("- {}", problem);
+    }
+    Err(format!(
+        "Found {} problem(s), the request was not sent",
+        problems.len()
+    )
+    .into())
+}
+
+enum Timeout {
+    Connect,
+    Read,
+    Total(Duration),
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Timeout::Connect => f.write_str("connection timed out"),
+            Timeout::Read => f.write_str("read timed out"),
+            Timeout::Total(timeout) => write!(
+                f,
+                "request timed out after {} seconds",
+                timeout.as_secs_f64()
+            ),
+        }
+    }
+}
+
+// Printed like the string errors used everywhere else
+impl fmt::Debug for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl Error for Timeout {}
+
+// reqwest and hyper errors are opaque, so point out what went wrong
+fn explain_error(err: Box<dyn Error>) -> Box<dyn Error> {
+    let causes = || {
+        let mut source: Option<&(dyn Error + 'static)> = Some(err.as_ref());
+        std::iter::from_fn(move || {
+            let cause = source?;
+            source = cause.source();
+            Some(cause.to_string())
+        })
+    };
+    if causes().any(|cause| cause.contains("operation timed out")) {
+        if causes().any(|cause| cause.contains("error trying to connect")) {
+            return Box::new(Timeout::Connect);
+        }
+        return Box::new(Timeout::Read);
+    }
+
+    for cause in causes() {
+        if cause.contains("message head is too large") {
+            return "The server sent response headers that are too large \
+                (more than 100 headers or about 400 KB), which yahc can't parse"
+                .into();
+        }
+        if cause.contains("invalid HTTP header parsed") {
+            return "The server sent a malformed response header".into();
+        }
+    }
+    err
+}
@@ -0,0 +1,85 @@
+use reqwest::blocking::Response;
+use reqwest::header::LINK;
+use reqwest::Url;
+
+// Finds the rel="next" target in Link headers such as
+// `<https://api.example.com/items?page=2>; rel="next", <...>; rel="last"`, see RFC 8288
+pub fn next_page(response: &Response) -> Option<Url> {
+    response
+        .headers()
+        .get_all(LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(parse_links)
+        .find(|(_, rels)| rels.iter().any(|rel| rel.eq_ignore_ascii_case("next")))
+        .and_then(|(target, _)| response.url().join(&target).ok())
+}
+
+fn parse_links(value: &str) -> Vec<(String, Vec<String>)> {
+    let mut links = vec![];
+    let mut rest = value;
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let target = rest[start + 1..end].to_string();
+        rest = &rest[end + 1..];
+
+        // Parameters run until the next comma that isn't inside quotes
+        let mut in_quotes = false;
+        let params_end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                c == ',' && !in_quotes
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let mut rels = vec![];
+        for param in rest[..params_end].split(';') {
+            let mut param = param.splitn(2, '=');
+            if param.next().map(str::trim) == Some("rel") {
+                let value = param.next().unwrap_or("").trim().trim_matches('"');
+                rels.extend(value.split_whitespace().map(str::to_string));
+            }
+        }
+        links.push((target, rels));
+        rest = &rest[params_end..];
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(target: &str, rels: &[&str]) -> (String, Vec<String>) {
+        let rels = rels.iter().map(|rel| rel.to_string()).collect();
+        (target.to_string(), rels)
+    }
+
+    #[test]
+    fn parse_link_header() {
+        assert_eq!(
+            parse_links(
+                r#"<https://api.example.com/items?page=2>; rel="next", </items?page=9>; rel=last"#
+            ),
+            vec![
+                link("https://api.example.com/items?page=2", &["next"]),
+                link("/items?page=9", &["last"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_link_parameters() {
+        // Commas inside quoted parameters don't start a new link
+        assert_eq!(
+            parse_links(r#"<a>; title="x, y"; rel="next prev", <b>"#),
+            vec![link("a", &["next", "prev"]), link("b", &[])]
+        );
+        assert_eq!(parse_links("<unterminated; rel=next"), vec![]);
+    }
+}
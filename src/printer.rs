@@ -1,23 +1,73 @@
+use std::error::Error;
 use std::fmt::Write;
+use std::io::{self, Write as IoWrite};
+use std::time::{Duration, Instant};
 
+use ansi_term::{Color, Style};
 use atty::Stream;
+use mime::Mime;
 use reqwest::blocking::{Request, Response};
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, SET_COOKIE};
+use reqwest::{Method, StatusCode};
+use terminal_size::{terminal_size, Width};
 
-use crate::utils::{colorize, get_content_type, indent_json};
-use crate::{Opt, Pretty, Theme};
+use crate::cookies::Cookie;
+use crate::diff::Line;
+use crate::utils::{
+    annotate_types, colorize, decode_text, get_content_type, hexdump, indent_json, indent_xml,
+    read_body, read_raw_body, read_sized_body, scope_color, split_multipart, truncate_strings,
+    BodySize,
+};
+use crate::{Opt, Pretty, Theme, Wrap};
 
 pub struct Printer {
     indent_json: bool,
     color: bool,
     theme: Theme,
     sort_headers: bool,
+    annotate_types: bool,
+    truncate_values: Option<usize>,
+    binary_decode: bool,
+    hexdump_limit: Option<usize>,
+    raw: bool,
+    wrap_width: Option<usize>,
+    stall_timeout: Option<Duration>,
 }
 
 impl Printer {
     pub fn new(opt: &Opt) -> Printer {
         let pretty = opt.pretty.as_ref().unwrap_or(&Pretty::All);
         let theme = opt.theme.as_ref().unwrap_or(&Theme::Auto);
+        let hexdump_limit = if opt.hexdump {
+            Some(opt.hexdump_limit)
+        } else {
+            None
+        };
+
+        let wrap_width = match opt.wrap.unwrap_or(Wrap::Auto) {
+            Wrap::None => None,
+            Wrap::Auto if atty::is(Stream::Stdout) => terminal_size()
+                .map(|(Width(width), _)| width as usize)
+                .filter(|width| *width > WRAP_INDENT.len()),
+            Wrap::Auto => None,
+            Wrap::Width(width) => Some(width),
+        };
+
+        if opt.raw_output {
+            return Printer {
+                indent_json: false,
+                color: false,
+                theme: theme.clone(),
+                sort_headers: false,
+                annotate_types: false,
+                truncate_values: None,
+                binary_decode: false,
+                hexdump_limit: None,
+                raw: true,
+                wrap_width: None,
+                stall_timeout: opt.stall_timeout,
+            };
+        }
 
         match pretty {
             Pretty::All => Printer {
@@ -25,43 +75,85 @@ impl Printer {
                 color: atty::is(Stream::Stdout),
                 theme: theme.clone(),
                 sort_headers: true,
+                annotate_types: opt.annotate_types,
+                truncate_values: opt.truncate_values,
+                binary_decode: !opt.no_binary_decode,
+                hexdump_limit,
+                raw: false,
+                wrap_width,
+                stall_timeout: opt.stall_timeout,
             },
             Pretty::Colors => Printer {
                 indent_json: false,
                 color: atty::is(Stream::Stdout),
                 theme: theme.clone(),
                 sort_headers: false,
+                annotate_types: opt.annotate_types,
+                truncate_values: opt.truncate_values,
+                binary_decode: !opt.no_binary_decode,
+                hexdump_limit,
+                raw: false,
+                wrap_width,
+                stall_timeout: opt.stall_timeout,
             },
             Pretty::Format => Printer {
                 indent_json: true,
                 color: false,
                 theme: theme.clone(),
                 sort_headers: true,
+                annotate_types: opt.annotate_types,
+                truncate_values: opt.truncate_values,
+                binary_decode: !opt.no_binary_decode,
+                hexdump_limit,
+                raw: false,
+                wrap_width,
+                stall_timeout: opt.stall_timeout,
             },
             Pretty::None => Printer {
                 indent_json: false,
                 color: false,
                 theme: theme.clone(),
                 sort_headers: false,
+                annotate_types: false,
+                truncate_values: None,
+                binary_decode: !opt.no_binary_decode,
+                hexdump_limit,
+                raw: false,
+                wrap_width,
+                stall_timeout: opt.stall_timeout,
             },
         }
     }
 
     fn print_json(&self, text: &str) {
-        match (self.indent_json, self.color) {
-            (true, true) => colorize(&indent_json(text), "json", &self.theme)
-                .for_each(|line| print!("{}", line)),
-            (false, true) => {
-                colorize(text, "json", &self.theme).for_each(|line| print!("{}", line))
+        let truncated;
+        let text = match self.truncate_values {
+            Some(max) => {
+                truncated = truncate_strings(text, max);
+                &truncated
             }
-            (true, false) => print!("{}", indent_json(text)),
-            (false, false) => print!("{}", text),
+            None => text,
+        };
+        let text = match (self.annotate_types, self.indent_json) {
+            (true, _) => annotate_types(&indent_json(text)),
+            (false, true) => indent_json(text),
+            (false, false) => text.to_string(),
+        };
+        if self.color {
+            colorize(&text, "json", &self.theme).for_each(|line| print!("{}", line))
+        } else {
+            print!("{}", text)
         }
     }
 
     fn print_xml(&self, text: &str) {
+        let text = if self.indent_json {
+            indent_xml(text)
+        } else {
+            text.to_string()
+        };
         if self.color {
-            colorize(text, "xml", &self.theme).for_each(|line| print!("{}", line))
+            colorize(&text, "xml", &self.theme).for_each(|line| print!("{}", line))
         } else {
             print!("{}", text)
         }
@@ -76,82 +168,208 @@ impl Printer {
     }
 
     fn print_binary_suppressor(&self) {
-        print!("+-----------------------------------------+\n");
-        print!("| NOTE: binary data not shown in terminal |\n");
+        println!("+-----------------------------------------+");
+        println!("| NOTE: binary data not shown in terminal |");
         print!("+-----------------------------------------+");
     }
 
+    fn print_hexdump(&self, body: &[u8], limit: usize) {
+        if body.len() > limit {
+            print!("{}", hexdump(&body[..limit]));
+            print!("... {} more bytes not shown", body.len() - limit);
+        } else {
+            print!("{}", hexdump(body).trim_end());
+        }
+    }
+
+    fn print_raw_head(&self, head: &str) {
+        print!("{}", raw_head(head));
+    }
+
+    fn print_raw_body(&self, body: &[u8]) {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(body).unwrap();
+        stdout.flush().unwrap();
+    }
+
     fn print_multipart_suppressor(&self) {
-        print!("+--------------------------------------------+\n");
-        print!("| NOTE: multipart data not shown in terminal |\n");
+        println!("+--------------------------------------------+");
+        println!("| NOTE: multipart data not shown in terminal |");
         print!("+--------------------------------------------+");
     }
 
-    fn headers_to_string(&self, headers: &HeaderMap, sort: bool) -> String {
-        let mut headers: Vec<(&HeaderName, &HeaderValue)> = headers.iter().collect();
-        if sort {
-            headers.sort_by(|(a, _), (b, _)| a.to_string().cmp(&b.to_string()))
-        }
+    fn print_stream_suppressor(&self) {
+        println!("+-------------------------------------------+");
+        println!("| NOTE: streamed body not shown in terminal |");
+        print!("+-------------------------------------------+");
+    }
 
-        let mut header_string = String::new();
-        for (key, value) in headers {
-            let key = key.to_string();
-            let value = value.to_str().unwrap();
-            writeln!(&mut header_string, "{}: {}", key, value).unwrap();
+    fn wrap(&self, text: &str) -> String {
+        match self.wrap_width {
+            Some(width) => wrap_lines(text, width),
+            None => text.to_string(),
         }
+    }
 
-        header_string
+    pub fn print_request_query(&self, request: &Request) {
+        let query = match request.url().query() {
+            Some(query) if !self.raw => query,
+            _ => return,
+        };
+        if self.color {
+            println!("{} {}\n", Style::new().bold().paint("Query string:"), query);
+        } else {
+            println!("Query string: {}\n", query);
+        }
     }
 
     pub fn print_request_headers(&self, request: &Request) {
-        let method = request.method();
-        let url = request.url();
-        let query_string = url.query().map_or(String::from(""), |q| ["?", q].concat());
-        let version = reqwest::Version::HTTP_11;
-        let mut headers = request.headers().clone();
-
-        // See https://github.com/seanmonstar/reqwest/issues/1030
-        if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
-            let content_length = HeaderValue::from_str(&body.len().to_string()).unwrap();
-            headers.insert(CONTENT_LENGTH, content_length);
+        let head = request_head(request, self.sort_headers);
+        if self.raw {
+            self.print_raw_head(&head);
+        } else if self.color {
+            colorize(&head, "http", &self.theme).for_each(|line| print!("{}", self.wrap(&line)));
+            println!("\x1b[0m");
+        } else {
+            println!("{}", self.wrap(&head));
         }
+    }
 
-        let request_line = format!("{} {}{} {:?}\n", method, url.path(), query_string, version);
-        let headers = &self.headers_to_string(&headers, self.sort_headers);
+    pub fn print_response_headers(&self, response: &Response) {
+        let status = response.status();
+        let status_line = status_line(response.version(), status);
+        let headers = headers_to_string(response.headers(), self.sort_headers);
 
-        if self.color {
-            colorize(&(request_line + &headers), "http", &self.theme)
-                .for_each(|line| print!("{}", line));
+        if self.raw {
+            self.print_raw_head(&(status_line + &headers));
+        } else if self.color {
+            let status_line = status_color(status, &self.theme)
+                .bold()
+                .paint(status_line)
+                .to_string();
+            print!("{}", self.wrap(&status_line));
+            colorize(&headers, "http", &self.theme).for_each(|line| print!("{}", self.wrap(&line)));
             println!("\x1b[0m");
         } else {
-            println!("{}", &(request_line + &headers));
+            println!("{}", self.wrap(&(status_line + &headers)));
         }
     }
 
-    pub fn print_response_headers(&self, response: &Response) {
-        let version = response.version();
-        let status = response.status();
-        let headers = response.headers();
+    pub fn print_response_cookies(&self, response: &Response) {
+        let cookies: Vec<Cookie> = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| Cookie::parse(&String::from_utf8_lossy(value.as_bytes())))
+            .collect();
+        if cookies.is_empty() {
+            return;
+        }
 
-        let status_line = format!(
-            "{:?} {} {}\n",
-            version,
-            status.as_str(),
-            status.canonical_reason().unwrap()
-        );
-        let headers = self.headers_to_string(headers, self.sort_headers);
+        let mut rows = vec![[
+            "Name".to_string(),
+            "Value".to_string(),
+            "Domain".to_string(),
+            "Path".to_string(),
+            "Expires".to_string(),
+            "Flags".to_string(),
+        ]];
+        for cookie in cookies {
+            let flags = cookie.flags();
+            rows.push([
+                cookie.name,
+                cookie.value,
+                cookie.domain.unwrap_or_else(|| "-".to_string()),
+                cookie.path.unwrap_or_else(|| "-".to_string()),
+                cookie.expires.unwrap_or_else(|| "session".to_string()),
+                flags,
+            ]);
+        }
+
+        let mut widths = [0; 6];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        for (i, row) in rows.iter().enumerate() {
+            let line: Vec<String> = row
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect();
+            let line = line.join("  ").trim_end().to_string();
+            if i == 0 && self.color {
+                println!("{}", Style::new().bold().paint(line));
+            } else {
+                println!("{}", line);
+            }
+        }
+        println!();
+    }
 
+    // DNS, connect and TLS times aren't exposed by reqwest's blocking client
+    pub fn print_meta(&self, first_byte: Duration, total: Duration, size: Option<&BodySize>) {
+        // Would end up in the middle of the body
+        if self.raw {
+            return;
+        }
+        for (label, value) in meta_rows(first_byte, total, size) {
+            let label = format!("{}:", label);
+            let label = format!("{:20}", label);
+            if self.color {
+                println!("{}{}", Color::Cyan.paint(label), value);
+            } else {
+                println!("{}{}", label, value);
+            }
+        }
+        println!();
+    }
+
+    pub fn print_response_header_values(&self, response: &Response, names: &[String]) {
+        for value in header_values(response.headers(), names) {
+            println!("{}", value);
+        }
+    }
+
+    pub fn print_diff(&self, lines: &[Line]) {
+        for line in lines {
+            let (prefix, text, color) = match line {
+                Line::Same(text) => (' ', text, None),
+                Line::Removed(text) => ('-', text, Some(Color::Red)),
+                Line::Added(text) => ('+', text, Some(Color::Green)),
+            };
+            let line = format!("{}{}", prefix, text);
+            match color {
+                Some(color) if self.color => println!("{}", color.paint(line)),
+                _ => println!("{}", line),
+            }
+        }
+        println!();
+    }
+
+    pub fn print_json_note(&self, items: usize) {
+        if self.raw {
+            return;
+        }
+        let note = format!("request body serialized as JSON from {} data items", items);
         if self.color {
-            colorize(&(status_line + &headers), "http", &self.theme)
-                .for_each(|line| print!("{}", line));
-            println!("\x1b[0m");
+            println!("{}", Color::Fixed(8).paint(note));
         } else {
-            println!("{}", &(status_line + &headers));
+            println!("{}", note);
         }
     }
 
     pub fn print_request_body(&self, request: &Request) {
-        let content_type = match get_content_type(&request.headers()) {
+        if self.raw {
+            if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+                self.print_raw_body(body);
+            }
+            return;
+        }
+
+        let content_type = match get_content_type(request.headers()) {
             Some(content_type) => content_type,
             None => return,
         };
@@ -159,12 +377,22 @@ impl Printer {
         if let Some(body) = request.body() {
             if content_type.contains("multipart") {
                 self.print_multipart_suppressor();
-            } else if content_type.contains("json") {
-                let body = &String::from_utf8(body.as_bytes().unwrap().into()).unwrap();
-                self.print_json(body);
+            } else if let Some(bytes) = body.as_bytes() {
+                match std::str::from_utf8(bytes) {
+                    Ok(text) if !self.is_binary(content_type) => {
+                        if content_type.contains("json") {
+                            self.print_json(text);
+                        } else {
+                            print!("{}", text);
+                        }
+                    }
+                    _ => match self.hexdump_limit {
+                        Some(limit) => self.print_hexdump(bytes, limit),
+                        None => self.print_binary_suppressor(),
+                    },
+                }
             } else {
-                let body = &String::from_utf8(body.as_bytes().unwrap().into()).unwrap();
-                print!("{}", body);
+                self.print_stream_suppressor();
             }
         }
 
@@ -175,28 +403,547 @@ impl Printer {
         }
     }
 
-    pub fn print_response_body(&self, response: Response) {
-        let content_type = match get_content_type(&response.headers()) {
-            Some(content_type) => content_type,
-            None => return,
-        };
+    fn is_binary(&self, content_type: &str) -> bool {
+        let binary_json = content_type.contains("cbor") || content_type.contains("msgpack");
+        (binary_json && !self.binary_decode)
+            || (!content_type.starts_with("multipart/")
+                && !content_type.contains("application")
+                && !content_type.contains("text"))
+    }
 
-        if !content_type.contains("application") && !content_type.contains("text") {
-            self.print_binary_suppressor();
-        } else if content_type.contains("json") {
-            self.print_json(&response.text().unwrap());
+    fn print_body(&self, body: &[u8], content_type: &str) {
+        if self.is_binary(content_type) {
+            match self.hexdump_limit {
+                Some(limit) => self.print_hexdump(body, limit),
+                None => self.print_binary_suppressor(),
+            }
+        } else if content_type.starts_with("multipart/") {
+            self.print_multipart(body, content_type);
+        } else if content_type.contains("cbor") || content_type.contains("msgpack") {
+            self.print_binary_json(body, content_type);
+        } else {
+            self.print_text(body, content_type);
+        }
+    }
+
+    fn print_binary_json(&self, body: &[u8], content_type: &str) {
+        match decode_binary_json(body, content_type) {
+            Ok(text) => self.print_json(&text),
+            Err(err) => {
+                println!("Failed to decode {}: {}", content_type, err);
+                match self.hexdump_limit {
+                    Some(limit) => self.print_hexdump(body, limit),
+                    None => self.print_binary_suppressor(),
+                }
+            }
+        }
+    }
+
+    fn print_text(&self, body: &[u8], content_type: &str) {
+        let text = decode_text(body, content_type);
+        if content_type.contains("json") {
+            self.print_json(&text);
         } else if content_type.contains("xml") {
-            self.print_xml(&response.text().unwrap());
+            self.print_xml(&text);
         } else if content_type.contains("html") {
-            self.print_html(&response.text().unwrap());
+            self.print_html(&text);
+        } else if content_type.contains("x-www-form-urlencoded") && self.indent_json {
+            self.print_form(&text);
         } else {
-            print!("{}", &response.text().unwrap());
+            print!("{}", &text);
+        }
+    }
+
+    fn print_form(&self, text: &str) {
+        match format_form(text, self.color) {
+            Some(form) => print!("{}", form),
+            None => print!("{}", text),
+        }
+    }
+
+    fn print_multipart(&self, body: &[u8], content_type: &str) {
+        let boundary = content_type
+            .parse::<Mime>()
+            .ok()
+            .and_then(|mime| mime.get_param("boundary").map(|b| b.to_string()));
+        let parts = match boundary {
+            Some(boundary) => split_multipart(body, &boundary),
+            None => vec![],
+        };
+        if parts.is_empty() {
+            self.print_multipart_suppressor();
+            return;
+        }
+
+        for (i, (headers, body)) in parts.iter().enumerate() {
+            let headers = String::from_utf8_lossy(headers);
+            // Parts without a Content-Type are text/plain, see RFC 2046 section 5.1
+            let content_type = headers
+                .lines()
+                .filter_map(|line| {
+                    let mut header = line.splitn(2, ':');
+                    match (header.next(), header.next()) {
+                        (Some(name), Some(value)) if name.eq_ignore_ascii_case("content-type") => {
+                            Some(value.trim().to_string())
+                        }
+                        _ => None,
+                    }
+                })
+                .next()
+                .unwrap_or_else(|| "text/plain".to_string());
+
+            if i > 0 {
+                print!("\n\n");
+            }
+            let label = format!("--- part {} ({}) ---", i + 1, content_type);
+            if self.color {
+                println!("{}", Style::new().bold().paint(label));
+            } else {
+                println!("{}", label);
+            }
+            for line in headers.lines() {
+                println!("{}", line);
+            }
+            println!();
+
+            self.print_body(body, &content_type);
+        }
+    }
+
+    pub fn print_response_body(
+        &self,
+        response: Response,
+        method: &Method,
+        deadline: Option<Instant>,
+    ) -> Result<Option<BodySize>, Box<dyn Error>> {
+        // Still encoded, to match the Content-Encoding header printed with it
+        if self.raw {
+            self.print_raw_body(&read_raw_body(response, self.stall_timeout, deadline)?);
+            return Ok(None);
         }
 
+        if let Some(note) = no_body_note(method, response.status()) {
+            println!("{}\n", note);
+            return Ok(None);
+        }
+
+        let content_type = match get_content_type(response.headers()) {
+            Some(content_type) => content_type.to_string(),
+            None => return Ok(None),
+        };
+
+        // Don't download a binary body only to say it isn't shown
+        let size = if self.is_binary(&content_type) && self.hexdump_limit.is_none() {
+            self.print_binary_suppressor();
+            None
+        } else {
+            let (body, size) = read_sized_body(response, self.stall_timeout, deadline)?;
+            self.print_body(&body, &content_type);
+            Some(size)
+        };
+
         if self.color {
             print!("\x1b[0m\n\n");
         } else {
             print!("\n\n");
         }
+        Ok(size)
+    }
+
+    pub fn read_json_page(
+        &self,
+        response: Response,
+        url: &reqwest::Url,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+        let body = read_body(response, self.stall_timeout, deadline)?;
+        match serde_json::from_slice(&body) {
+            Ok(serde_json::Value::Array(items)) => Ok(items),
+            _ => Err(format!("The page at {} is not a JSON array", url).into()),
+        }
+    }
+
+    pub fn print_json_value(&self, value: &serde_json::Value) {
+        if self.raw {
+            self.print_raw_body(value.to_string().as_bytes());
+            return;
+        }
+        self.print_json(&value.to_string());
+        if self.color {
+            print!("\x1b[0m\n\n");
+        } else {
+            print!("\n\n");
+        }
+    }
+}
+
+// CBOR and MessagePack bodies as JSON text
+fn decode_binary_json(body: &[u8], content_type: &str) -> Result<String, String> {
+    let value: serde_json::Value = if content_type.contains("cbor") {
+        serde_cbor::from_slice(body).map_err(|e| e.to_string())?
+    } else {
+        rmp_serde::from_slice(body).map_err(|e| e.to_string())?
+    };
+    Ok(value.to_string())
+}
+
+// One decoded `key = value` pair per line with the keys padded to line up
+fn format_form(text: &str, color: bool) -> Option<String> {
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_str(text.trim()).ok()?;
+    let width = pairs
+        .iter()
+        .map(|(key, _)| key.chars().count())
+        .max()
+        .unwrap_or(0);
+    let lines: Vec<String> = pairs
+        .iter()
+        .map(|(key, value)| {
+            let key = format!("{:width$}", key, width = width);
+            let line = if color {
+                format!("{} = {}", Color::Cyan.paint(key), value)
+            } else {
+                format!("{} = {}", key, value)
+            };
+            line.trim_end().to_string()
+        })
+        .collect();
+    Some(lines.join("\n"))
+}
+
+// The request line and headers, each line ending with a \n
+fn request_head(request: &Request, sort: bool) -> String {
+    let url = request.url();
+    let query_string = url.query().map_or(String::from(""), |q| ["?", q].concat());
+    let version = reqwest::Version::HTTP_11;
+    let mut headers = request.headers().clone();
+
+    // See https://github.com/seanmonstar/reqwest/issues/1030
+    if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+        let content_length = HeaderValue::from_str(&body.len().to_string()).unwrap();
+        headers.insert(CONTENT_LENGTH, content_length);
+    }
+
+    let request_line = format!(
+        "{} {}{} {:?}\n",
+        request.method(),
+        url.path(),
+        query_string,
+        version
+    );
+    request_line + &headers_to_string(&headers, sort)
+}
+
+fn status_line(version: reqwest::Version, status: StatusCode) -> String {
+    let status_line = format!(
+        "{:?} {} {}",
+        version,
+        status.as_str(),
+        status.canonical_reason().unwrap_or("")
+    );
+    status_line.trim_end().to_string() + "\n"
+}
+
+// Every value of each named header, in the order the names were given
+fn header_values(headers: &HeaderMap, names: &[String]) -> Vec<String> {
+    names
+        .iter()
+        .flat_map(|name| headers.get_all(name.as_str()))
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .collect()
+}
+
+// Timings, and with a body read the sizes before and after decoding it
+fn meta_rows(
+    first_byte: Duration,
+    total: Duration,
+    size: Option<&BodySize>,
+) -> Vec<(&'static str, String)> {
+    let seconds = |duration: Duration| format!("{:.3}s", duration.as_secs_f64());
+    let mut rows = vec![
+        ("Time to first byte", seconds(first_byte)),
+        ("Total elapsed", seconds(total)),
+    ];
+    match size {
+        Some(size) if size.encoded => {
+            rows.push(("Compressed size", format!("{} bytes", size.received)));
+            rows.push(("Decompressed size", format!("{} bytes", size.decoded)));
+            if size.received > 0 {
+                let ratio = size.decoded as f64 / size.received as f64;
+                rows.push(("Compression ratio", format!("{:.2}", ratio)));
+            }
+        }
+        Some(size) => rows.push(("Body size", format!("{} bytes", size.decoded))),
+        None => {}
+    }
+    rows
+}
+
+// Responses that can't carry a body get a note instead of an empty section
+fn no_body_note(method: &Method, status: StatusCode) -> Option<&'static str> {
+    if method == Method::HEAD
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+    {
+        Some("(no body)")
+    } else {
+        None
+    }
+}
+
+// A head as it's sent on the wire: CRLF line endings and a blank line at the end
+fn raw_head(head: &str) -> String {
+    format!("{}\r\n", head.replace('\n', "\r\n"))
+}
+
+// Values aren't necessarily ASCII, servers may send obs-text (bytes above 0x7f)
+fn headers_to_string(headers: &HeaderMap, sort: bool) -> String {
+    let mut headers: Vec<(&HeaderName, &HeaderValue)> = headers.iter().collect();
+    if sort {
+        headers.sort_by_key(|(name, _)| name.to_string())
+    }
+
+    let mut header_string = String::new();
+    for (key, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        writeln!(&mut header_string, "{}: {}", key, value).unwrap();
+    }
+
+    header_string
+}
+
+fn status_color(status: StatusCode, theme: &Theme) -> Color {
+    let scope = if status.is_success() {
+        "markup.status.success.http"
+    } else if status.is_redirection() {
+        "markup.status.redirection.http"
+    } else if status.is_client_error() {
+        "markup.status.client-error.http"
+    } else if status.is_server_error() {
+        "markup.status.server-error.http"
+    } else {
+        "markup.status.informational.http"
+    };
+    scope_color(scope, theme)
+}
+
+pub const WRAP_INDENT: &str = "    ";
+
+// Continuation lines are indented so they can't be mistaken for another header.
+// ANSI escape sequences take no columns, and the color they set carries over the break.
+fn wrap_lines(text: &str, width: usize) -> String {
+    let mut wrapped = String::new();
+    let mut column = 0;
+    let mut in_escape = false;
+    for c in text.chars() {
+        if in_escape {
+            in_escape = c != 'm';
+        } else if c == '\x1b' {
+            in_escape = true;
+        } else if c == '\n' {
+            column = 0;
+        } else if c != '\r' {
+            if column >= width {
+                wrapped.push('\n');
+                wrapped.push_str(WRAP_INDENT);
+                column = WRAP_INDENT.len();
+            }
+            column += 1;
+        }
+        wrapped.push(c);
+    }
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_request_head() {
+        let url = "http://example.com/items?a=1".parse().unwrap();
+        let mut request = Request::new(Method::POST, url);
+        let headers = request.headers_mut();
+        headers.insert("host", HeaderValue::from_static("example.com"));
+        headers.insert("accept", HeaderValue::from_static("*/*"));
+        *request.body_mut() = Some("{}".into());
+        assert_eq!(
+            raw_head(&request_head(&request, true)),
+            "POST /items?a=1 HTTP/1.1\r\n\
+             accept: */*\r\n\
+             content-length: 2\r\n\
+             host: example.com\r\n\
+             \r\n"
+        );
+    }
+
+    #[test]
+    fn extract_two_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+        headers.append("set-cookie", HeaderValue::from_static("a=1"));
+        headers.append("set-cookie", HeaderValue::from_static("b=2"));
+        headers.insert("server", HeaderValue::from_static("test"));
+        let names = vec!["Set-Cookie".to_string(), "content-type".to_string()];
+        assert_eq!(
+            header_values(&headers, &names),
+            vec!["a=1", "b=2", "text/plain"]
+        );
+        // Missing headers print nothing
+        let names = vec!["etag".to_string(), "server".to_string()];
+        assert_eq!(header_values(&headers, &names), vec!["test"]);
+    }
+
+    #[test]
+    fn bodiless_responses() {
+        assert_eq!(
+            no_body_note(&Method::HEAD, StatusCode::OK),
+            Some("(no body)")
+        );
+        assert_eq!(
+            no_body_note(&Method::GET, StatusCode::NO_CONTENT),
+            Some("(no body)")
+        );
+        assert_eq!(
+            no_body_note(&Method::GET, StatusCode::NOT_MODIFIED),
+            Some("(no body)")
+        );
+        assert_eq!(no_body_note(&Method::GET, StatusCode::OK), None);
+        assert_eq!(no_body_note(&Method::POST, StatusCode::NOT_FOUND), None);
+    }
+
+    #[test]
+    fn meta_for_gzip_response() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let text = "a".repeat(1000);
+        let mut gzip = GzEncoder::new(vec![], Compression::default());
+        gzip.write_all(text.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let received = gzip.len();
+        let response: Response = http::Response::builder()
+            .header("content-encoding", "gzip")
+            .body(gzip)
+            .unwrap()
+            .into();
+
+        let (body, size) = read_sized_body(response, None, None).unwrap();
+        assert_eq!(body, text.as_bytes());
+        assert_eq!(
+            (size.received, size.decoded, size.encoded),
+            (received, 1000, true)
+        );
+
+        let second = Duration::from_secs(1);
+        let rows = meta_rows(second, second * 2, Some(&size));
+        let rows: Vec<_> = rows
+            .iter()
+            .map(|(label, value)| (*label, value.as_str()))
+            .collect();
+        let ratio = 1000.0 / received as f64;
+        assert!(ratio > 10.0);
+        assert_eq!(
+            rows,
+            [
+                ("Time to first byte", "1.000s"),
+                ("Total elapsed", "2.000s"),
+                ("Compressed size", format!("{} bytes", received).as_str()),
+                ("Decompressed size", "1000 bytes"),
+                ("Compression ratio", format!("{:.2}", ratio).as_str()),
+            ]
+        );
+
+        // Uncompressed bodies have only one size
+        let size = BodySize {
+            received: 5,
+            decoded: 5,
+            encoded: false,
+        };
+        let rows = meta_rows(second, second, Some(&size));
+        assert_eq!(rows[2], ("Body size", "5 bytes".to_string()));
+        assert_eq!(meta_rows(second, second, None).len(), 2);
+    }
+
+    #[test]
+    fn raw_status_line() {
+        let line = status_line(reqwest::Version::HTTP_11, StatusCode::NOT_FOUND);
+        assert_eq!(raw_head(&line), "HTTP/1.1 404 Not Found\r\n\r\n");
+        // Unknown status codes have no reason phrase
+        let status = StatusCode::from_u16(599).unwrap();
+        let line = status_line(reqwest::Version::HTTP_2, status);
+        assert_eq!(raw_head(&line), "HTTP/2.0 599\r\n\r\n");
+    }
+
+    #[test]
+    fn decode_cbor_and_msgpack() {
+        let value = serde_json::json!({"id": 7, "tags": ["a", "b"], "ok": true, "none": null});
+        let expected = [
+            r#"{"#,
+            r#"    "id": 7,"#,
+            r#"    "none": null,"#,
+            r#"    "ok": true,"#,
+            r#"    "tags": ["#,
+            r#"        "a","#,
+            r#"        "b""#,
+            r#"    ]"#,
+            r#"}"#,
+        ]
+        .join("\n");
+
+        let cbor = serde_cbor::to_vec(&value).unwrap();
+        let text = decode_binary_json(&cbor, "application/cbor").unwrap();
+        assert_eq!(indent_json(&text).trim_end(), expected);
+
+        let msgpack = rmp_serde::to_vec(&value).unwrap();
+        let text = decode_binary_json(&msgpack, "application/x-msgpack").unwrap();
+        assert_eq!(indent_json(&text).trim_end(), expected);
+
+        assert!(decode_binary_json(b"\xff\xff", "application/cbor").is_err());
+    }
+
+    #[test]
+    fn format_urlencoded_form() {
+        assert_eq!(
+            format_form("name=J%C3%BCrgen+M&id=7&id=8&empty=\n", false).as_deref(),
+            Some("name  = Jürgen M\nid    = 7\nid    = 8\nempty =")
+        );
+        assert_eq!(format_form("a=%ZZ", false).as_deref(), Some("a = %ZZ"));
+    }
+
+    #[test]
+    fn headers_with_obs_text() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-b", HeaderValue::from_bytes(b"caf\xe9").unwrap());
+        headers.insert("x-a", HeaderValue::from_static("1"));
+        assert_eq!(
+            headers_to_string(&headers, true),
+            "x-a: 1\nx-b: caf\u{fffd}\n"
+        );
+    }
+
+    #[test]
+    fn wrap_long_lines() {
+        assert_eq!(wrap_lines("abcdefgh\nab", 6), "abcdef\n    gh\nab");
+        assert_eq!(wrap_lines("abcdefghij", 6), "abcdef\n    gh\n    ij");
+    }
+
+    #[test]
+    fn wrap_ignores_escape_sequences() {
+        assert_eq!(
+            wrap_lines("\x1b[31mabcdefgh\x1b[0m", 6),
+            "\x1b[31mabcdef\n    gh\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn status_colors_from_theme() {
+        let not_found = StatusCode::NOT_FOUND;
+        assert_eq!(status_color(StatusCode::OK, &Theme::Auto), Color::Green);
+        assert_eq!(status_color(not_found, &Theme::Auto), Color::Yellow);
+        assert_eq!(
+            status_color(not_found, &Theme::Solarized),
+            Color::Fixed(136)
+        );
     }
 }
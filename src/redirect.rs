@@ -0,0 +1,330 @@
+use std::error::Error;
+use std::time::Instant;
+
+use reqwest::blocking::{Client, Request, Response};
+use reqwest::header::{
+    HeaderValue, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST,
+    LOCATION, PROXY_AUTHORIZATION, SET_COOKIE, TRANSFER_ENCODING, WWW_AUTHENTICATE,
+};
+use reqwest::{Method, StatusCode, Url};
+
+use crate::cookies::Cookie;
+use crate::RedirectPolicy;
+
+// What happened on the way to the final response
+#[derive(Default)]
+pub struct Hops {
+    // The Set-Cookie headers of every response, with the URL of the response
+    pub set_cookies: Vec<(Url, HeaderValue)>,
+    // When the request for the final response was sent. The client's timeout
+    // applies to each request on its own, so reading the body is timed from here.
+    pub sent: Option<Instant>,
+    // A copy of the request for the final response, unless its body was streamed
+    pub last_request: Option<Request>,
+}
+
+// reqwest's redirect::Policy can only decide whether a redirect is followed, so
+// redirects are followed here where the method and body of the next request can change.
+// Without `max_redirects` the redirect response itself is returned.
+pub fn execute(
+    client: &Client,
+    mut request: Request,
+    policy: RedirectPolicy,
+    max_redirects: Option<usize>,
+    hops: &mut Hops,
+) -> Result<Response, Box<dyn Error>> {
+    // The same URL may be requested again with another method, e.g. after a 303
+    let mut visited = vec![(request.method().clone(), request.url().clone())];
+    for _ in 0..=max_redirects.unwrap_or(0) {
+        // Streaming bodies can't be cloned, so their redirects can only drop the body
+        let cloned_request = request.try_clone();
+        let can_resend_body = cloned_request.is_some();
+        let mut next_request = cloned_request.unwrap_or_else(|| {
+            let mut next_request = Request::new(request.method().clone(), request.url().clone());
+            *next_request.headers_mut() = request.headers().clone();
+            next_request
+        });
+
+        hops.sent = Some(Instant::now());
+        let response = client.execute(request)?;
+        for set_cookie in response.headers().get_all(SET_COOKIE) {
+            hops.set_cookies
+                .push((response.url().clone(), set_cookie.clone()));
+        }
+        let status = response.status();
+        let location = match response.headers().get(LOCATION) {
+            Some(location) if is_redirect(status) && max_redirects.is_some() => location,
+            _ => {
+                hops.last_request = Some(next_request).filter(|_| can_resend_body);
+                return Ok(response);
+            }
+        };
+        let url = response.url().join(location.to_str()?)?;
+
+        let (method, preserve_body) = follow_up(policy, status, next_request.method());
+        if preserve_body && !can_resend_body {
+            return Ok(response);
+        } else if !preserve_body {
+            *next_request.body_mut() = None;
+            for header in &[
+                CONTENT_TYPE,
+                CONTENT_LENGTH,
+                CONTENT_ENCODING,
+                TRANSFER_ENCODING,
+            ] {
+                next_request.headers_mut().remove(header);
+            }
+        }
+        *next_request.method_mut() = method;
+
+        retarget(&mut next_request, url, response.url())?;
+        add_cookies(&mut next_request, &hops.set_cookies)?;
+
+        let next = (next_request.method().clone(), next_request.url().clone());
+        if let Some(cycle) = redirect_loop(&visited, &next) {
+            return Err(format!("Redirect loop detected: {}", cycle).into());
+        }
+        visited.push(next);
+
+        request = next_request;
+    }
+
+    Err(format!(
+        "Too many redirects (more than {})",
+        max_redirects.unwrap_or(0)
+    )
+    .into())
+}
+
+// The method of the request that follows a redirect, and whether it keeps the body
+fn follow_up(policy: RedirectPolicy, status: StatusCode, method: &Method) -> (Method, bool) {
+    let preserve_body = match policy {
+        RedirectPolicy::Standard => {
+            status == StatusCode::TEMPORARY_REDIRECT || status == StatusCode::PERMANENT_REDIRECT
+        }
+        RedirectPolicy::AllGet => false,
+        RedirectPolicy::Preserve => true,
+    };
+    if preserve_body || (policy != RedirectPolicy::AllGet && method == Method::HEAD) {
+        (method.clone(), preserve_body)
+    } else {
+        (Method::GET, false)
+    }
+}
+
+// The URLs from the first request of `next` on, when it was already requested
+// with the same method
+fn redirect_loop(visited: &[(Method, Url)], next: &(Method, Url)) -> Option<String> {
+    let start = visited.iter().position(|visited| visited == next)?;
+    let cycle: Vec<&str> = visited[start..]
+        .iter()
+        .chain(Some(next))
+        .map(|(_, url)| url.as_str())
+        .collect();
+    Some(cycle.join(" -> "))
+}
+
+// Points the request at another URL, and drops credentials when that is on another
+// origin: another scheme, host or port
+pub fn retarget(request: &mut Request, url: Url, from: &Url) -> Result<(), Box<dyn Error>> {
+    let headers = request.headers_mut();
+    let cross_origin = url.scheme() != from.scheme()
+        || url.host_str() != from.host_str()
+        || url.port_or_known_default() != from.port_or_known_default();
+    if cross_origin {
+        for header in &[AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, WWW_AUTHENTICATE] {
+            headers.remove(header);
+        }
+    }
+    if let Some(host) = url.host_str() {
+        // `port` is only set when it isn't the default for the scheme
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        headers.insert(HOST, HeaderValue::from_str(&host)?);
+    }
+    *request.url_mut() = url;
+    Ok(())
+}
+
+// Cookies set along the way are sent on to the host that set them, like a browser
+// would, so that e.g. a login that redirects keeps its session cookie
+fn add_cookies(
+    request: &mut Request,
+    set_cookies: &[(Url, HeaderValue)],
+) -> Result<(), Box<dyn Error>> {
+    let mut cookies: Vec<(String, String)> = match request.headers().get(COOKIE) {
+        Some(cookie) => cookie
+            .to_str()?
+            .split(';')
+            .filter_map(|pair| {
+                let mut pair = pair.splitn(2, '=');
+                Some((
+                    pair.next()?.trim().to_string(),
+                    pair.next()?.trim().to_string(),
+                ))
+            })
+            .collect(),
+        None => vec![],
+    };
+    let host = host_key(request.url());
+    let set_cookies = set_cookies
+        .iter()
+        .filter(|(url, _)| host_key(url) == host)
+        .filter_map(|(_, value)| Cookie::parse(value.to_str().ok()?));
+    for cookie in set_cookies {
+        cookies.retain(|(name, _)| *name != cookie.name);
+        if !cookie.is_expired() {
+            cookies.push((cookie.name, cookie.value));
+        }
+    }
+    if cookies.is_empty() {
+        request.headers_mut().remove(COOKIE);
+    } else {
+        let cookies: Vec<String> = cookies
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        request
+            .headers_mut()
+            .insert(COOKIE, HeaderValue::from_str(&cookies.join("; "))?);
+    }
+    Ok(())
+}
+
+// Cookies and credentials are kept apart per host and port
+pub fn host_key(url: &Url) -> String {
+    format!(
+        "{}:{}",
+        url.host_str().unwrap_or(""),
+        url.port_or_known_default().unwrap_or(0)
+    )
+}
+
+fn is_redirect(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        url.parse().unwrap()
+    }
+
+    fn retargeted(from: &str, to: &str) -> Request {
+        let mut request = Request::new(Method::GET, url(from));
+        for header in &[AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION] {
+            request
+                .headers_mut()
+                .insert(header, HeaderValue::from_static("secret"));
+        }
+        retarget(&mut request, url(to), &url(from)).unwrap();
+        request
+    }
+
+    fn credentials(request: &Request) -> usize {
+        [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION]
+            .iter()
+            .filter(|header| request.headers().contains_key(*header))
+            .count()
+    }
+
+    #[test]
+    fn follow_up_requests() {
+        let statuses = [
+            StatusCode::MOVED_PERMANENTLY,
+            StatusCode::FOUND,
+            StatusCode::SEE_OTHER,
+            StatusCode::TEMPORARY_REDIRECT,
+            StatusCode::PERMANENT_REDIRECT,
+        ];
+        let expected = [
+            (RedirectPolicy::Standard, [false, false, false, true, true]),
+            (RedirectPolicy::AllGet, [false; 5]),
+            (RedirectPolicy::Preserve, [true; 5]),
+        ];
+        for (policy, preserved) in &expected {
+            for (status, preserve) in statuses.iter().zip(preserved) {
+                let method = if *preserve { Method::POST } else { Method::GET };
+                assert_eq!(
+                    follow_up(*policy, *status, &Method::POST),
+                    (method, *preserve),
+                    "{:?} {}",
+                    policy,
+                    status
+                );
+            }
+        }
+
+        // HEAD stays HEAD, except with all-get
+        let found = StatusCode::FOUND;
+        let head = Method::HEAD;
+        assert_eq!(
+            follow_up(RedirectPolicy::Standard, found, &head),
+            (Method::HEAD, false)
+        );
+        assert_eq!(
+            follow_up(RedirectPolicy::AllGet, found, &head),
+            (Method::GET, false)
+        );
+    }
+
+    #[test]
+    fn redirect_loops() {
+        let (a, b) = (url("http://a.example/"), url("http://b.example/"));
+        let visited = vec![(Method::GET, a.clone()), (Method::GET, b.clone())];
+        assert_eq!(
+            redirect_loop(&visited, &(Method::GET, a.clone())).as_deref(),
+            Some("http://a.example/ -> http://b.example/ -> http://a.example/")
+        );
+        assert_eq!(
+            redirect_loop(&visited, &(Method::GET, b.clone())).as_deref(),
+            Some("http://b.example/ -> http://b.example/")
+        );
+        assert_eq!(
+            redirect_loop(&visited, &(Method::GET, url("http://c.example/"))),
+            None
+        );
+        // A 303 turns the POST into a GET, which is a new request to the same URL
+        let visited = vec![(Method::POST, a.clone())];
+        assert_eq!(redirect_loop(&visited, &(Method::GET, a)), None);
+    }
+
+    #[test]
+    fn cross_host_redirect_drops_credentials() {
+        let request = retargeted("http://a.example/login", "http://b.example/home");
+        assert_eq!(credentials(&request), 0);
+        assert_eq!(request.headers()[HOST], "b.example");
+        assert_eq!(request.url().as_str(), "http://b.example/home");
+    }
+
+    #[test]
+    fn same_host_redirect_keeps_credentials() {
+        let request = retargeted("http://a.example/login", "http://a.example:80/home");
+        assert_eq!(credentials(&request), 3);
+        assert_eq!(request.headers()[HOST], "a.example");
+    }
+
+    #[test]
+    fn port_or_scheme_change_is_cross_origin() {
+        let request = retargeted("http://a.example/", "http://a.example:8080/");
+        assert_eq!(credentials(&request), 0);
+        assert_eq!(request.headers()[HOST], "a.example:8080");
+
+        let request = retargeted("https://a.example/", "http://a.example/");
+        assert_eq!(credentials(&request), 0);
+        let request = retargeted("http://a.example:443/", "https://a.example/");
+        assert_eq!(credentials(&request), 0);
+        assert_eq!(request.headers()[HOST], "a.example");
+    }
+}
@@ -1,8 +1,16 @@
-use reqwest::blocking::multipart;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use reqwest::blocking::{self, multipart};
 use reqwest::header::{
     HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, CONNECTION, HOST,
 };
 
+use crate::template;
+use crate::utils::{self, run_command};
 use crate::{RequestItem, Url};
 
 pub struct RequestItems(Vec<RequestItem>);
@@ -11,6 +19,134 @@ pub enum Body {
     Json(serde_json::Map<String, serde_json::Value>),
     Form(Vec<(String, String)>),
     Multipart(multipart::Form),
+    Raw(Vec<u8>, String),
+    // Sent as it is read, with a Content-Length only when the size is known
+    Stream(blocking::Body, String),
+}
+
+impl Body {
+    // With `buffer` the file is read up front, so that the body can be sent again
+    pub fn from_file(body: &str, as_json: bool, buffer: bool) -> Result<Body, String> {
+        if body == "@clipboard" {
+            return Body::from_clipboard();
+        }
+
+        let path = match body.strip_prefix('@') {
+            Some(path) => Path::new(path),
+            None => return Err(format!("{:?} is not a valid --body, expected @FILE", body)),
+        };
+        if !as_json {
+            if path == Path::new("-") {
+                let content_type = "application/octet-stream".to_string();
+                return Ok(Body::Stream(blocking::Body::new(io::stdin()), content_type));
+            }
+            let content_type = mime_guess::from_path(path).first_or_octet_stream();
+            let content_type = content_type.to_string();
+            if buffer {
+                let content = fs::read(path)
+                    .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+                return Ok(Body::Raw(content, content_type));
+            }
+            let file = File::open(path)
+                .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+            return Ok(Body::Stream(file.into(), content_type));
+        }
+
+        let content =
+            fs::read(path).map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+
+        let text = String::from_utf8(content)
+            .map_err(|_| format!("{} is not valid UTF-8", path.display()))?;
+        let value: serde_json::Value = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&text).map_err(|e| e.to_string())?,
+            Some("toml") => toml::from_str(&text).map_err(|e| e.to_string())?,
+            Some("json") => serde_json::from_str(&text).map_err(|e| e.to_string())?,
+            _ => {
+                return Err(format!(
+                    "--as-json only supports YAML, TOML and JSON files, got {}",
+                    path.display()
+                ))
+            }
+        };
+        let content = serde_json::to_vec(&value).unwrap();
+        Ok(Body::Raw(content, "application/json".to_string()))
+    }
+
+    pub fn from_template(
+        template: &str,
+        data_file: Option<&str>,
+        allow_missing: bool,
+    ) -> Result<Body, String> {
+        let text = fs::read_to_string(template)
+            .map_err(|err| format!("Failed to read {}: {}", template, err))?;
+        let data = match data_file {
+            Some(data_file) => read_json_file(data_file, false)?,
+            None => serde_json::Value::Null,
+        };
+        let content = template::render(&text, &data, allow_missing)?;
+        let content_type = mime_guess::from_path(template).first_or_octet_stream();
+        Ok(Body::Raw(content.into_bytes(), content_type.to_string()))
+    }
+
+    // `variables` is the JSON body built from the data items, if there were any
+    pub fn from_graphql(query: &str, variables: Option<Body>) -> Result<Body, String> {
+        let query = match query.strip_prefix('@') {
+            Some("-") => {
+                let mut query = String::new();
+                io::stdin()
+                    .read_to_string(&mut query)
+                    .map_err(|err| format!("Failed to read the query from stdin: {}", err))?;
+                query
+            }
+            Some(path) => fs::read_to_string(path)
+                .map_err(|err| format!("Failed to read {}: {}", path, err))?,
+            None => query.to_string(),
+        };
+        let mut body = serde_json::Map::new();
+        body.insert("query".to_string(), serde_json::Value::String(query));
+        if let Some(Body::Json(variables)) = variables {
+            body.insert(
+                "variables".to_string(),
+                serde_json::Value::Object(variables),
+            );
+        }
+        Ok(Body::Json(body))
+    }
+
+    // Variables that aren't valid unicode are left out
+    pub fn from_env(prefix: &str) -> Body {
+        Body::from_vars(prefix, env::vars_os())
+    }
+
+    // Names keep their prefix, and variables that aren't valid UTF-8 are skipped
+    fn from_vars(prefix: &str, vars: impl Iterator<Item = (OsString, OsString)>) -> Body {
+        let variables = vars
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key, serde_json::Value::String(value)))
+            .collect();
+        Body::Json(variables)
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn from_clipboard() -> Result<Body, String> {
+        use clipboard::{ClipboardContext, ClipboardProvider};
+
+        let content = ClipboardContext::new()
+            .and_then(|mut ctx| ctx.get_contents())
+            .map_err(|err| format!("Failed to read the clipboard: {}", err))?;
+        let content_type = if serde_json::from_str::<serde_json::Value>(&content).is_ok() {
+            "application/json"
+        } else {
+            "text/plain; charset=utf-8"
+        };
+        Ok(Body::Raw(content.into_bytes(), content_type.to_string()))
+    }
+
+    #[cfg(not(feature = "clipboard"))]
+    fn from_clipboard() -> Result<Body, String> {
+        Err("yahc was built without clipboard support, use @./clipboard for a file".to_string())
+    }
 }
 
 impl RequestItems {
@@ -18,56 +154,173 @@ impl RequestItems {
         RequestItems(request_items)
     }
 
-    pub fn headers(&self, url: &Url) -> HeaderMap<HeaderValue> {
+    // Names of the headers given as request items, which have to be valid by now
+    pub fn header_names(&self) -> Vec<HeaderName> {
+        self.0
+            .iter()
+            .filter_map(|item| match item {
+                RequestItem::HttpHeader(key, _) | RequestItem::HttpHeaderFile(key, _) => {
+                    HeaderName::from_bytes(key.as_bytes()).ok()
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn data_items(&self) -> usize {
+        self.0
+            .iter()
+            .filter(|item| {
+                matches!(
+                    item,
+                    RequestItem::DataField(..)
+                        | RequestItem::DataFile(..)
+                        | RequestItem::JSONField(..)
+                        | RequestItem::JSONFile(..)
+                )
+            })
+            .count()
+    }
+
+    pub fn headers(&self, url: &Url) -> Result<HeaderMap<HeaderValue>, String> {
         let mut headers = HeaderMap::new();
         headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
-        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate"));
+        headers.insert(
+            ACCEPT_ENCODING,
+            HeaderValue::from_static(utils::ACCEPT_ENCODING),
+        );
         headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
-        headers.insert(HOST, HeaderValue::from_str(&url.host().unwrap()).unwrap());
+        let host = url.host().ok_or("The URL has no host")?;
+        let host = HeaderValue::from_str(&host)
+            .map_err(|_| format!("{:?} is not a valid value for the Host header", host))?;
+        headers.insert(HOST, host);
+        // A header given more than once is sent once for every value, but the
+        // first one still replaces the default above
+        let mut given = vec![];
+        for item in &self.0 {
+            let (key, value) = match item {
+                RequestItem::HttpHeader(key, value) => (key, value.clone()),
+                RequestItem::HttpHeaderFile(key, path) => (key, read_header_file(path)?),
+                _ => continue,
+            };
+            let name = header_name(key)?;
+            let value = header_value(key, &value)?;
+            if given.contains(&name) {
+                headers.append(name, value);
+            } else {
+                headers.insert(name.clone(), value);
+                given.push(name);
+            }
+        }
+        Ok(headers)
+    }
+
+    pub fn validate(&self, lenient: bool, allow_exec: bool) -> Vec<String> {
+        let mut problems = vec![];
         for item in &self.0 {
             match item {
                 RequestItem::HttpHeader(key, value) => {
-                    let key = HeaderName::from_bytes(&key.as_bytes()).unwrap();
-                    let value = HeaderValue::from_str(&value).unwrap();
-                    headers.insert(key, value);
+                    if let Err(err) = header_name(key) {
+                        problems.push(err);
+                    }
+                    if let Err(err) = header_value(key, value) {
+                        problems.push(err);
+                    }
+                }
+                RequestItem::HttpHeaderFile(key, path) => {
+                    if let Err(err) = header_name(key) {
+                        problems.push(err);
+                    }
+                    match read_header_file(path) {
+                        Ok(value) if header_value(key, &value).is_err() => problems.push(format!(
+                            "{} does not contain a valid value for {}",
+                            path, key
+                        )),
+                        Ok(_) => {}
+                        Err(err) => problems.push(err),
+                    }
+                }
+                RequestItem::JSONField(key, value) => {
+                    if let Err(err) = parse_json(value, lenient) {
+                        problems.push(format!("{}:={} is not valid JSON: {}", key, value, err));
+                    }
+                }
+                RequestItem::JSONFile(_, path) => {
+                    if let Err(err) = read_json_file(path, lenient) {
+                        problems.push(err);
+                    }
+                }
+                RequestItem::DataFile(key, path) => {
+                    match path.strip_prefix('!') {
+                        Some(_) if !allow_exec => problems.push(exec_not_allowed(key, path)),
+                        // Commands are only run when the body is built
+                        Some(_) => {}
+                        None => {
+                            if let Err(err) = read_data_file(path) {
+                                problems.push(err);
+                            }
+                        }
+                    }
+                }
+                RequestItem::FormFile(_, path) => {
+                    if !Path::new(path).is_file() {
+                        problems.push(format!("Upload file {} does not exist", path));
+                    }
                 }
-                _ => {}
+                RequestItem::UrlParam(_, _) | RequestItem::DataField(_, _) => {}
             }
         }
-        headers
+        problems
     }
 
     pub fn query(&self) -> Vec<(&String, &String)> {
         let mut query = vec![];
         for item in &self.0 {
-            match item {
-                RequestItem::UrlParam(key, value) => {
-                    query.push((key, value));
-                }
-                _ => {}
+            if let RequestItem::UrlParam(key, value) = item {
+                query.push((key, value));
             }
         }
         query
     }
 
-    pub fn body(&self, as_form: bool) -> Result<Option<Body>, &str> {
+    pub fn body(
+        &self,
+        as_form: bool,
+        lenient: bool,
+        allow_exec: bool,
+    ) -> Result<Option<Body>, String> {
         if !as_form {
             let mut body = serde_json::Map::new();
+            // Keys that became arrays because they were repeated
+            let mut repeated = vec![];
             for item in &self.0 {
                 match item.clone() {
                     RequestItem::JSONField(key, value) => {
-                        body.insert(key, value);
+                        let value = parse_json(&value, lenient).map_err(|err| {
+                            format!("{}:={} is not valid JSON: {}", key, value, err)
+                        })?;
+                        insert_json(&mut body, &key, value, false, &mut repeated)?;
                     }
                     RequestItem::DataField(key, value) => {
-                        body.insert(key, serde_json::Value::String(value));
+                        let value = serde_json::Value::String(value);
+                        insert_json(&mut body, &key, value, false, &mut repeated)?;
+                    }
+                    RequestItem::DataFile(key, path) => {
+                        let value = read_field_value(&key, &path, allow_exec)?;
+                        let value = serde_json::Value::String(value);
+                        insert_json(&mut body, &key, value, false, &mut repeated)?;
+                    }
+                    RequestItem::JSONFile(key, path) => {
+                        let value = read_json_file(&path, lenient)?;
+                        insert_json(&mut body, &key, value, true, &mut repeated)?;
                     }
                     RequestItem::FormFile(_, _) => {
-                        return Err("Sending Files is not supported when the request body is in JSON format");
+                        return Err("Sending Files is not supported when the request body is in JSON format".to_string());
                     }
                     _ => {}
                 }
             }
-            if body.len() > 0 {
+            if !body.is_empty() {
                 Ok(Some(Body::Json(body)))
             } else {
                 Ok(None)
@@ -77,10 +330,14 @@ impl RequestItems {
             let mut files = Vec::<(String, String)>::new();
             for item in &self.0 {
                 match item.clone() {
-                    RequestItem::JSONField(_, _) => {
-                        return Err("JSON values are not supported in Form fields");
+                    RequestItem::JSONField(_, _) | RequestItem::JSONFile(_, _) => {
+                        return Err("JSON values are not supported in Form fields".to_string());
                     }
                     RequestItem::DataField(key, value) => text_fields.push((key, value)),
+                    RequestItem::DataFile(key, path) => {
+                        let value = read_field_value(&key, &path, allow_exec)?;
+                        text_fields.push((key, value))
+                    }
                     RequestItem::FormFile(key, value) => files.push((key, value)),
                     _ => {}
                 }
@@ -94,7 +351,9 @@ impl RequestItems {
                         form = form.text(key, value);
                     }
                     for (key, value) in files {
-                        form = form.file(key, value).unwrap();
+                        form = form
+                            .file(key, &value)
+                            .map_err(|err| format!("Failed to read {}: {}", value, err))?;
                     }
                     Ok(Some(Body::Multipart(form)))
                 }
@@ -102,3 +361,601 @@ impl RequestItems {
         }
     }
 }
+
+// Editors usually end files with a newline, which is never meant to be part of the value
+fn header_name(key: &str) -> Result<HeaderName, String> {
+    HeaderName::from_bytes(key.as_bytes())
+        .map_err(|_| format!("{:?} is not a valid header name", key))
+}
+
+// http also accepts bytes above 0x7f, but servers disagree on how to decode
+// them, so values are limited to visible ASCII, spaces and tabs
+fn header_value(key: &str, value: &str) -> Result<HeaderValue, String> {
+    let invalid = || {
+        format!(
+            "{:?} is not a valid value for {}, expected visible ASCII",
+            value, key
+        )
+    };
+    if !value
+        .chars()
+        .all(|c| c.is_ascii_graphic() || c == ' ' || c == '\t')
+    {
+        return Err(invalid());
+    }
+    HeaderValue::from_str(value).map_err(|_| invalid())
+}
+
+fn read_header_file(path: &str) -> Result<String, String> {
+    let value =
+        fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+    Ok(value.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+// `field=@!command` uses the output of the command like `$(command)` in a shell,
+// without trailing newlines
+fn read_field_value(key: &str, path: &str, allow_exec: bool) -> Result<String, String> {
+    match path.strip_prefix('!') {
+        Some(command) if allow_exec => Ok(run_command(command)?
+            .trim_end_matches(&['\r', '\n'][..])
+            .to_string()),
+        Some(_) => Err(exec_not_allowed(key, path)),
+        None => read_data_file(path),
+    }
+}
+
+fn exec_not_allowed(key: &str, path: &str) -> String {
+    format!("{}=@{} runs a command, which needs --allow-exec", key, path)
+}
+
+// Unlike header files, the contents are used exactly as they are
+fn read_data_file(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))
+}
+
+fn read_json_file(path: &str, lenient: bool) -> Result<serde_json::Value, String> {
+    let text =
+        fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+    parse_json(&text, lenient).map_err(|err| format!("{} is not valid JSON: {}", path, err))
+}
+
+fn parse_json(text: &str, lenient: bool) -> serde_json::Result<serde_json::Value> {
+    if lenient {
+        serde_json::from_str(&strict_json(text))
+    } else {
+        serde_json::from_str(text)
+    }
+}
+
+// Rewrites single-quoted strings as double-quoted ones and drops trailing commas,
+// anything else is left for serde_json to accept or reject
+fn strict_json(text: &str) -> String {
+    let mut strict = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                strict.push(c);
+                while let Some(c) = chars.next() {
+                    strict.push(c);
+                    match c {
+                        '\\' => strict.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '\'' => {
+                strict.push('"');
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => match chars.next() {
+                            Some('\'') => strict.push('\''),
+                            Some(escaped) => {
+                                strict.push('\\');
+                                strict.push(escaped);
+                            }
+                            None => {}
+                        },
+                        '"' => strict.push_str("\\\""),
+                        '\'' => break,
+                        _ => strict.push(c),
+                    }
+                }
+                strict.push('"');
+            }
+            ',' => {
+                let rest = chars.clone().find(|c| !c.is_whitespace());
+                if rest != Some('}') && rest != Some(']') {
+                    strict.push(c);
+                }
+            }
+            _ => strict.push(c),
+        }
+    }
+    strict
+}
+
+enum PathPart {
+    Key(String),
+    Index(usize),
+    Append,
+}
+
+// Splits `user[roles][]`, `items[0]` and `settings.theme` into their parts,
+// `\` escapes a `[`, `]` or `.` that is part of a key
+fn parse_json_path(key: &str) -> Result<Vec<PathPart>, String> {
+    let invalid = || format!("{:?} is not a valid JSON path", key);
+    let mut parts = vec![];
+    let mut chars = key.chars();
+    let mut name = String::new();
+    let mut in_brackets = false;
+    // Set right after a `]`, where the next part may start without a key before it
+    let mut closed = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '[' | '.' if !in_brackets => {
+                if !closed {
+                    if name.is_empty() {
+                        return Err(invalid());
+                    }
+                    parts.push(PathPart::Key(std::mem::take(&mut name)));
+                }
+                in_brackets = c == '[';
+                closed = false;
+            }
+            ']' if in_brackets => {
+                let part = if name.is_empty() {
+                    PathPart::Append
+                } else if name.chars().all(|c| c.is_ascii_digit()) {
+                    PathPart::Index(name.parse().map_err(|_| invalid())?)
+                } else {
+                    PathPart::Key(name.clone())
+                };
+                name.clear();
+                parts.push(part);
+                in_brackets = false;
+                closed = true;
+            }
+            '[' | ']' => return Err(invalid()),
+            _ if closed => return Err(invalid()),
+            '\\' => name.push(chars.next().ok_or_else(invalid)?),
+            _ => name.push(c),
+        }
+    }
+    if in_brackets || (!closed && name.is_empty()) {
+        return Err(invalid());
+    }
+    if !closed {
+        parts.push(PathPart::Key(name));
+    }
+    Ok(parts)
+}
+
+fn insert_json(
+    body: &mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+    value: serde_json::Value,
+    merge: bool,
+    repeated: &mut Vec<String>,
+) -> Result<(), String> {
+    let path = parse_json_path(key)?;
+    let mut root = serde_json::Value::Object(std::mem::take(body));
+    let result = insert_at(&mut root, &path, value, merge, key, "", repeated);
+    if let serde_json::Value::Object(root) = root {
+        *body = root;
+    }
+    result
+}
+
+// Creates objects and arrays along `path` as needed, but never replaces a
+// value set by an earlier request item with one of a different shape.
+// `pointer` is the JSON pointer of `target`, which is how `repeated` refers to arrays.
+fn insert_at(
+    target: &mut serde_json::Value,
+    path: &[PathPart],
+    value: serde_json::Value,
+    merge: bool,
+    key: &str,
+    pointer: &str,
+    repeated: &mut Vec<String>,
+) -> Result<(), String> {
+    use serde_json::Value;
+
+    let conflict = || format!("{} conflicts with an earlier request item", key);
+    let part = match path.first() {
+        Some(part) => part,
+        None => {
+            let is_container = |value: &Value| value.is_object() || value.is_array();
+            if target.is_null() {
+                *target = value;
+            } else if merge {
+                *target = merge_json(target.take(), value, key)?;
+            } else if is_container(target) || is_container(&value) {
+                return Err(conflict());
+            } else {
+                *target = value;
+            }
+            return Ok(());
+        }
+    };
+
+    if target.is_null() {
+        *target = match part {
+            PathPart::Key(_) => Value::Object(serde_json::Map::new()),
+            PathPart::Index(_) | PathPart::Append => Value::Array(vec![]),
+        };
+    }
+    let (next, pointer) = match (part, target) {
+        // Repeating a key collects its values into an array, in the order given.
+        // Only single values are collected, and only into arrays made that way.
+        (PathPart::Key(name), Value::Object(object))
+            if path.len() == 1 && !merge && object.contains_key(name) =>
+        {
+            let pointer = format!("{}/{}", pointer, escape_pointer(name));
+            let existing = object.get_mut(name).unwrap();
+            let is_container = |value: &Value| value.is_object() || value.is_array();
+            match existing {
+                _ if is_container(&value) => return Err(conflict()),
+                Value::Array(array) if repeated.contains(&pointer) => array.push(value),
+                _ if is_container(existing) => return Err(conflict()),
+                _ => {
+                    *existing = Value::Array(vec![existing.take(), value]);
+                    repeated.push(pointer);
+                }
+            }
+            return Ok(());
+        }
+        (PathPart::Key(name), Value::Object(object)) => (
+            object.entry(name.clone()).or_insert(Value::Null),
+            format!("{}/{}", pointer, escape_pointer(name)),
+        ),
+        (PathPart::Index(index), Value::Array(array)) => {
+            if array.len() <= *index {
+                array.resize(index + 1, Value::Null);
+            }
+            (&mut array[*index], format!("{}/{}", pointer, index))
+        }
+        (PathPart::Append, Value::Array(array)) => {
+            array.push(Value::Null);
+            let pointer = format!("{}/{}", pointer, array.len() - 1);
+            (array.last_mut().unwrap(), pointer)
+        }
+        _ => return Err(conflict()),
+    };
+    insert_at(next, &path[1..], value, merge, key, &pointer, repeated)
+}
+
+// See RFC 6901
+fn escape_pointer(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+// Objects are merged key by key, any other value in `overlay` replaces the one in `base`
+fn merge_json(
+    base: serde_json::Value,
+    overlay: serde_json::Value,
+    path: &str,
+) -> Result<serde_json::Value, String> {
+    use serde_json::Value;
+
+    match (base, overlay) {
+        (Value::Object(mut base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let value = match base.remove(&key) {
+                    Some(existing) => merge_json(existing, value, &format!("{}.{}", path, key))?,
+                    None => value,
+                };
+                base.insert(key, value);
+            }
+            Ok(Value::Object(base))
+        }
+        (Value::Object(_), _) | (_, Value::Object(_)) => Err(format!(
+            "Cannot merge an object with a non-object value at {}",
+            path
+        )),
+        (_, overlay) => Ok(overlay),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strict_json_quotes_and_commas() {
+        assert_eq!(
+            strict_json(r#"{'a': 'it\'s "b"', "c": [1, 2,], }"#),
+            r#"{"a": "it's \"b\"", "c": [1, 2] }"#
+        );
+        // Commas and quotes inside double-quoted strings are kept
+        assert_eq!(strict_json(r#"["a,]", "'"]"#), r#"["a,]", "'"]"#);
+    }
+
+    #[test]
+    fn lenient_json() {
+        let value = parse_json("{'a': [1,],}", true).unwrap();
+        assert_eq!(value, serde_json::json!({"a": [1]}));
+        assert!(parse_json("{'a': 1}", false).is_err());
+    }
+
+    fn items(items: &[&str]) -> RequestItems {
+        RequestItems::new(items.iter().map(|item| item.parse().unwrap()).collect())
+    }
+
+    fn json_body(items: &[&str]) -> Result<serde_json::Value, String> {
+        match self::items(items).body(false, false, false)? {
+            Some(Body::Json(body)) => Ok(serde_json::Value::Object(body)),
+            _ => Ok(serde_json::Value::Null),
+        }
+    }
+
+    #[test]
+    fn nested_paths() {
+        let body = json_body(&[
+            "user[name]=a",
+            "user[roles][]=x",
+            "user[roles][]=y",
+            "items[1]:=2",
+            "settings.theme=dark",
+            r"a\.b=c",
+        ])
+        .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "user": {"name": "a", "roles": ["x", "y"]},
+                "items": [null, 2],
+                "settings": {"theme": "dark"},
+                "a.b": "c",
+            })
+        );
+    }
+
+    #[test]
+    fn nested_path_errors() {
+        assert!(json_body(&["user=a", "user[name]=b"]).is_err());
+        assert!(json_body(&["user[name]=a", "user[0]=b"]).is_err());
+        assert!(json_body(&["user[name=a"]).is_err());
+        assert!(json_body(&["[name]=a"]).is_err());
+    }
+
+    #[test]
+    fn repeated_keys() {
+        let body = json_body(&["tags=a", "tags=b", "ids:=1", "ids:=2", "ids:=3"]).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"tags": ["a", "b"], "ids": [1, 2, 3]})
+        );
+        let body = json_body(&["a[b]=1", "a.b=2", "a[b]=3"]).unwrap();
+        assert_eq!(body, serde_json::json!({"a": {"b": ["1", "2", "3"]}}));
+        assert!(json_body(&["user:={}", "user=a"]).is_err());
+    }
+
+    #[test]
+    fn repeated_keys_with_arrays() {
+        assert!(json_body(&["a:=1", "a:=[2]"]).is_err());
+        assert!(json_body(&["a:=[1]", "a:=2"]).is_err());
+        assert!(json_body(&["a:=[1,2]", "a:=[3]"]).is_err());
+        assert!(json_body(&["a:=1", "a:=2", "a:=[3]"]).is_err());
+        // [] appends whatever the value is
+        let body = json_body(&["a[]:=1", "a[]:=2"]).unwrap();
+        assert_eq!(body, serde_json::json!({"a": [1, 2]}));
+    }
+
+    #[test]
+    fn invalid_header_values() {
+        let url = Url::new("http://example.com".to_string(), None).unwrap();
+        let items = items(&["X-A:café"]);
+        assert_eq!(
+            items.headers(&url).unwrap_err(),
+            r#""café" is not a valid value for X-A, expected visible ASCII"#
+        );
+        assert_eq!(items.validate(false, false).len(), 1);
+
+        let headers = self::items(&["X-A:a b\tc"]).headers(&url).unwrap();
+        assert_eq!(headers["x-a"], "a b\tc");
+    }
+
+    #[test]
+    fn buffered_body_file() {
+        let path = env::temp_dir().join(format!("yahc-body-{}.bin", std::process::id()));
+        fs::write(&path, b"\x00\x01body").unwrap();
+        let body = format!("@{}", path.display());
+        match Body::from_file(&body, false, true) {
+            Ok(Body::Raw(content, content_type)) => {
+                assert_eq!(content, b"\x00\x01body");
+                assert_eq!(content_type, "application/octet-stream");
+            }
+            _ => panic!("expected a buffered body"),
+        }
+        assert!(matches!(
+            Body::from_file(&body, false, false),
+            Ok(Body::Stream(..))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn merge_json_objects() {
+        let base = serde_json::json!({"a": {"b": 1, "c": [1]}, "d": "x"});
+        let overlay = serde_json::json!({"a": {"c": [2], "e": null}, "f": true});
+        assert_eq!(
+            merge_json(base, overlay, "body"),
+            Ok(serde_json::json!({"a": {"b": 1, "c": [2], "e": null}, "d": "x", "f": true}))
+        );
+    }
+
+    #[test]
+    fn merge_json_files() {
+        let dir = env::temp_dir();
+        let first = dir.join(format!("yahc-merge-1-{}.json", std::process::id()));
+        let second = dir.join(format!("yahc-merge-2-{}.json", std::process::id()));
+        fs::write(&first, r#"{"name": "a", "tags": [1], "db": {"host": "x"}}"#).unwrap();
+        fs::write(&second, r#"{"tags": [2], "db": {"port": 5432}}"#).unwrap();
+        let body = json_body(&[
+            &format!("config:=@{}", first.display()),
+            &format!("config:=@{}", second.display()),
+        ]);
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+        assert_eq!(
+            body,
+            Ok(serde_json::json!({
+                "config": {"name": "a", "tags": [2], "db": {"host": "x", "port": 5432}}
+            }))
+        );
+    }
+
+    #[test]
+    fn merge_json_conflicts() {
+        let base = serde_json::json!({"a": {"b": 1}});
+        assert_eq!(
+            merge_json(base, serde_json::json!({"a": 2}), "body"),
+            Err("Cannot merge an object with a non-object value at body.a".to_string())
+        );
+        let base = serde_json::json!({"a": [1]});
+        assert_eq!(
+            merge_json(base, serde_json::json!({"a": {"b": 1}}), "body"),
+            Err("Cannot merge an object with a non-object value at body.a".to_string())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn field_value_from_command() {
+        assert_eq!(
+            read_field_value("a", "!printf 'out\\n\\n'", true),
+            Ok("out".to_string())
+        );
+        assert!(read_field_value("a", "!exit 1", true).is_err());
+        assert_eq!(
+            read_field_value("a", "!printf out", false),
+            Err("a=@!printf out runs a command, which needs --allow-exec".to_string())
+        );
+    }
+
+    #[test]
+    fn body_from_env_vars() {
+        let vars = vec![
+            ("APP_NAME", "yahc"),
+            ("APP_", ""),
+            ("OTHER_APP_PORT", "1"),
+            ("app_lower", "x"),
+        ];
+        let vars = vars
+            .into_iter()
+            .map(|(key, value)| (OsString::from(key), OsString::from(value)));
+        match Body::from_vars("APP_", vars) {
+            Body::Json(body) => assert_eq!(
+                serde_json::Value::Object(body),
+                serde_json::json!({"APP_NAME": "yahc", "APP_": ""})
+            ),
+            _ => panic!("expected a JSON body"),
+        }
+    }
+
+    #[test]
+    fn yaml_body_file() {
+        let path = env::temp_dir().join(format!("yahc-body-{}.yaml", std::process::id()));
+        fs::write(&path, "name: yahc\ntags:\n  - a\n  - 1\n").unwrap();
+        let body = format!("@{}", path.display());
+        let as_is = Body::from_file(&body, false, true);
+        let converted = Body::from_file(&body, true, false);
+        fs::remove_file(&path).unwrap();
+
+        match as_is {
+            Ok(Body::Raw(content, content_type)) => {
+                assert_eq!(content, b"name: yahc\ntags:\n  - a\n  - 1\n");
+                assert_eq!(content_type, "text/x-yaml");
+            }
+            _ => panic!("expected the file as it is"),
+        }
+        match converted {
+            Ok(Body::Raw(content, content_type)) => {
+                let value: serde_json::Value = serde_json::from_slice(&content).unwrap();
+                assert_eq!(value, serde_json::json!({"name": "yahc", "tags": ["a", 1]}));
+                assert_eq!(content_type, "application/json");
+            }
+            _ => panic!("expected the file converted to JSON"),
+        }
+    }
+
+    #[test]
+    fn as_json_unsupported_extension() {
+        let path = env::temp_dir().join(format!("yahc-body-{}.txt", std::process::id()));
+        fs::write(&path, "a: 1").unwrap();
+        let body = Body::from_file(&format!("@{}", path.display()), true, false);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            body.err(),
+            Some(format!(
+                "--as-json only supports YAML, TOML and JSON files, got {}",
+                path.display()
+            ))
+        );
+    }
+
+    #[test]
+    fn header_from_file() {
+        let url = Url::new("http://example.com".to_string(), None).unwrap();
+        let path = env::temp_dir().join(format!("yahc-header-{}.txt", std::process::id()));
+        fs::write(&path, "Bearer abc\r\n").unwrap();
+        let item = format!("Authorization:@{}", path.display());
+        let items = items(&[&item]);
+        let headers = items.headers(&url);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            format!("{:?}", items.0),
+            format!(
+                "[HttpHeaderFile(\"Authorization\", {:?})]",
+                path.display().to_string()
+            )
+        );
+        assert_eq!(headers.unwrap()["authorization"], "Bearer abc");
+    }
+
+    #[test]
+    fn header_from_missing_file() {
+        let url = Url::new("http://example.com".to_string(), None).unwrap();
+        let items = items(&["X-A:@/nonexistent/yahc-header"]);
+        let err = items.headers(&url).unwrap_err();
+        assert!(
+            err.starts_with("Failed to read /nonexistent/yahc-header"),
+            "{}",
+            err
+        );
+        assert_eq!(items.validate(false, false), vec![err]);
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let items = items(&[
+            "file@/nonexistent/upload.bin",
+            "X A:1",
+            "X-B:a\u{7f}",
+            "a:={",
+            "ok=1",
+        ]);
+        assert_eq!(
+            items.validate(false, false),
+            vec![
+                "Upload file /nonexistent/upload.bin does not exist".to_string(),
+                r#""X A" is not a valid header name"#.to_string(),
+                r#""a\u{7f}" is not a valid value for X-B, expected visible ASCII"#.to_string(),
+                "a:={ is not valid JSON: EOF while parsing an object at line 1 column 1"
+                    .to_string(),
+            ]
+        );
+        assert!(self::items(&["a=1", "X-A:1"])
+            .validate(false, false)
+            .is_empty());
+    }
+
+    #[test]
+    fn count_data_items() {
+        let items = items(&["a=1", "b:=2", "c=@file", "d:=@file.json", "X-A:1", "q==1"]);
+        assert_eq!(items.data_items(), 4);
+    }
+}
@@ -0,0 +1,224 @@
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::blocking::{Request, Response};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Method, StatusCode};
+
+const MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+pub struct Retries {
+    pub count: usize,
+    pub delay: Duration,
+    pub all_methods: bool,
+}
+
+// Resends the request after connection errors and responses that say the server
+// is temporarily unavailable, doubling the delay after every attempt.
+pub fn execute<F>(
+    request: Request,
+    retries: &Retries,
+    mut send: F,
+) -> Result<Response, Box<dyn Error>>
+where
+    F: FnMut(Request) -> Result<Response, Box<dyn Error>>,
+{
+    let can_retry = retries.all_methods || is_idempotent(request.method());
+    let mut delay = retries.delay;
+    let mut request = request;
+    for attempt in 1.. {
+        // Streaming bodies can't be sent twice
+        let next_request = match request.try_clone() {
+            Some(next_request) if can_retry && attempt <= retries.count => next_request,
+            _ => return send(request),
+        };
+        let (reason, wait) = match send(request) {
+            Ok(response) if is_transient(response.status()) => {
+                let wait = retry_after(response.status(), response.headers(), Utc::now());
+                let wait = wait.unwrap_or(delay);
+                (response.status().to_string(), wait)
+            }
+            Ok(response) => return Ok(response),
+            // Errors from yahc itself, such as a redirect loop, won't go away on retry
+            Err(err) if err.downcast_ref::<reqwest::Error>().is_none() => return Err(err),
+            Err(err) => (err.to_string(), delay),
+        };
+        eprintln!(
+            "{}, retrying in {} seconds ({} of {})",
+            reason,
+            wait.as_secs_f64(),
+            attempt,
+            retries.count
+        );
+        thread::sleep(wait);
+        delay = next_delay(delay);
+        request = next_request;
+    }
+    unreachable!()
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+// Doubling stops at an hour, unless --retry-delay was longer to begin with
+fn next_delay(delay: Duration) -> Duration {
+    match delay.checked_mul(2) {
+        Some(doubled) if doubled <= MAX_DELAY => doubled,
+        _ => delay.max(MAX_DELAY),
+    }
+}
+
+// Retry-After is either a number of seconds or an HTTP date. It's only honored
+// on 429 and 503, and never makes yahc wait longer than an hour.
+fn retry_after(status: StatusCode, headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let wait = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = DateTime::parse_from_rfc2822(value).ok()?;
+            let millis = (date.with_timezone(&Utc) - now).num_milliseconds().max(0);
+            Duration::from_millis(millis as u64)
+        }
+    };
+    Some(wait.min(MAX_DELAY))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    use chrono::TimeZone;
+    use reqwest::blocking::Client;
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    fn headers(retry_after: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static(retry_after));
+        headers
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_an_hour() {
+        let mut delay = Duration::from_secs(1);
+        let mut delays = vec![];
+        for _ in 0..14 {
+            delays.push(delay.as_secs());
+            delay = next_delay(delay);
+        }
+        assert_eq!(
+            delays,
+            [1, 2, 4, 8, 16, 32, 64, 128, 256, 512, 1024, 2048, 3600, 3600]
+        );
+        // A longer --retry-delay is kept as it is
+        let long = Duration::from_secs(2 * 60 * 60);
+        assert_eq!(next_delay(long), long);
+        assert_eq!(
+            next_delay(Duration::from_secs(u64::MAX)),
+            Duration::from_secs(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn retry_after_seconds_and_dates() {
+        let now = Utc.ymd(2015, 10, 21).and_hms(7, 28, 0);
+        let unavailable = StatusCode::SERVICE_UNAVAILABLE;
+        assert_eq!(
+            retry_after(unavailable, &headers("120"), now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            retry_after(
+                StatusCode::TOO_MANY_REQUESTS,
+                &headers("Wed, 21 Oct 2015 07:29:30 GMT"),
+                now
+            ),
+            Some(Duration::from_secs(90))
+        );
+        // Dates in the past mean right away
+        assert_eq!(
+            retry_after(unavailable, &headers("Wed, 21 Oct 2015 07:00:00 GMT"), now),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(retry_after(unavailable, &headers("soon"), now), None);
+    }
+
+    #[test]
+    fn retry_after_is_capped_and_scoped() {
+        let now = Utc::now();
+        assert_eq!(
+            retry_after(StatusCode::SERVICE_UNAVAILABLE, &headers("999999999"), now),
+            Some(MAX_DELAY)
+        );
+        assert_eq!(
+            retry_after(StatusCode::BAD_GATEWAY, &headers("5"), now),
+            None
+        );
+        assert_eq!(
+            retry_after(StatusCode::GATEWAY_TIMEOUT, &headers("5"), now),
+            None
+        );
+    }
+
+    #[test]
+    fn retry_after_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            // The first attempt never gets an answer
+            let (stalled, _) = listener.accept().unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 1024]).unwrap();
+            // The retry is slow too, but still within its own timeout
+            thread::sleep(Duration::from_millis(300));
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                .unwrap();
+            drop(stalled);
+        });
+
+        let timeout = Duration::from_millis(500);
+        let client = Client::builder().timeout(timeout).build().unwrap();
+        let retries = Retries {
+            count: 1,
+            delay: Duration::from_millis(10),
+            all_methods: false,
+        };
+        let started = Instant::now();
+        let mut attempts = vec![];
+        let response = execute(client.get(&url).build().unwrap(), &retries, |request| {
+            let result = client.execute(request);
+            attempts.push(result.as_ref().err().map(|err| err.is_timeout()));
+            result.map_err(Into::into)
+        })
+        .unwrap();
+        server.join().unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().unwrap(), "ok");
+        assert_eq!(attempts, [Some(true), None]);
+        assert!(started.elapsed() > timeout + Duration::from_millis(300));
+    }
+}
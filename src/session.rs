@@ -0,0 +1,349 @@
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE};
+use reqwest::Url;
+use serde_json::{Map, Value};
+
+use crate::cookies::Cookie;
+use crate::redirect::{host_key, Hops};
+
+// Cookies and headers that carry over between invocations, stored as
+// {"headers": {name: value}, "credentials": {host: authorization},
+// "cookies": {host: {name: value}}}. Credentials and cookies are only ever
+// sent back to the host and port they came from.
+pub struct Session {
+    path: PathBuf,
+    headers: Map<String, Value>,
+    credentials: Map<String, Value>,
+    cookies: Map<String, Value>,
+}
+
+impl Session {
+    // Named sessions are kept per host so that one name can be reused across APIs
+    pub fn named(name: &str, host: &str) -> Result<Session, String> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || !name.chars().all(valid) {
+            return Err(format!(
+                "{:?} is not a valid session name, use --session-file for paths",
+                name
+            ));
+        }
+        let dir = dirs::config_dir().ok_or("Could not find a config directory for sessions")?;
+        let host = host.replace(':', "_");
+        Session::from_file(
+            dir.join("yahc")
+                .join("sessions")
+                .join(host)
+                .join(format!("{}.json", name)),
+        )
+    }
+
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<Session, String> {
+        let path = path.into();
+        if !path.exists() {
+            return Ok(Session {
+                path,
+                headers: Map::new(),
+                credentials: Map::new(),
+                cookies: Map::new(),
+            });
+        }
+        let invalid =
+            |err: &dyn Display| format!("{} is not a valid session file: {}", path.display(), err);
+        let contents = fs::read_to_string(&path).map_err(|err| invalid(&err))?;
+        let mut contents: Map<String, Value> =
+            serde_json::from_str(&contents).map_err(|err| invalid(&err))?;
+        let mut field = |key: &str| match contents.remove(key) {
+            Some(Value::Object(map)) => Ok(map),
+            Some(_) => Err(invalid(&format!("{:?} must be an object", key))),
+            None => Ok(Map::new()),
+        };
+        let headers = field("headers")?;
+        let credentials = field("credentials")?;
+        let cookies = field("cookies")?;
+        Ok(Session {
+            path,
+            headers,
+            credentials,
+            cookies,
+        })
+    }
+
+    // Headers given on the command line win over the ones saved in the session,
+    // and so do credentials given with --auth
+    pub fn apply(
+        &self,
+        headers: &mut HeaderMap,
+        given: &[HeaderName],
+        url: &Url,
+        has_auth: bool,
+    ) -> Result<(), String> {
+        for (key, value) in &self.headers {
+            let key = HeaderName::from_bytes(key.as_bytes())
+                .map_err(|_| format!("Invalid header name {:?} in session", key))?;
+            let value = value
+                .as_str()
+                .and_then(|value| HeaderValue::from_str(value).ok())
+                .ok_or_else(|| format!("Invalid value for {} in session", key))?;
+            if !given.contains(&key) && key != AUTHORIZATION {
+                headers.insert(key, value);
+            }
+        }
+
+        let host = host_key(url);
+        if !has_auth && !given.contains(&AUTHORIZATION) {
+            if let Some(credentials) = self.credentials.get(&host) {
+                let credentials = credentials
+                    .as_str()
+                    .and_then(|value| HeaderValue::from_str(value).ok())
+                    .ok_or_else(|| format!("Invalid credentials for {} in session", host))?;
+                headers.insert(AUTHORIZATION, credentials);
+            }
+        }
+
+        let mut cookies = match headers.get(COOKIE) {
+            Some(cookie) => cookie.to_str().unwrap_or("").to_string(),
+            None => String::new(),
+        };
+        let given_cookies: Vec<String> = cookies
+            .split(';')
+            .filter_map(|pair| pair.split('=').next())
+            .map(|name| name.trim().to_string())
+            .collect();
+        let saved = self
+            .cookies
+            .get(&host)
+            .and_then(|cookies| cookies.as_object());
+        for (name, value) in saved.into_iter().flatten() {
+            if given_cookies.contains(name) {
+                continue;
+            }
+            if !cookies.is_empty() {
+                cookies.push_str("; ");
+            }
+            cookies.push_str(&format!("{}={}", name, value.as_str().unwrap_or("")));
+        }
+        if !cookies.is_empty() {
+            let cookies = HeaderValue::from_str(&cookies)
+                .map_err(|_| "Invalid cookie in session".to_string())?;
+            headers.insert(COOKIE, cookies);
+        }
+        Ok(())
+    }
+
+    // Remembers the headers given on the command line, any credentials that were
+    // sent, and the cookies set or cleared by every response, redirects included
+    pub fn update(&mut self, sent: &HeaderMap, given: &[HeaderName], url: &Url, hops: &Hops) {
+        for (key, value) in sent {
+            let name = key.as_str();
+            let value = match value.to_str() {
+                Ok(value) => Value::String(value.to_string()),
+                Err(_) => continue,
+            };
+            if *key == AUTHORIZATION {
+                self.credentials.insert(host_key(url), value);
+            } else if given.contains(key)
+                && *key != COOKIE
+                && !name.starts_with("content-")
+                && !name.starts_with("if-")
+            {
+                self.headers.insert(key.to_string(), value);
+            }
+        }
+
+        for (url, set_cookie) in &hops.set_cookies {
+            let cookie = match set_cookie.to_str().ok().and_then(Cookie::parse) {
+                Some(cookie) => cookie,
+                None => continue,
+            };
+            let cookies = self
+                .cookies
+                .entry(host_key(url))
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(cookies) = cookies {
+                if cookie.is_expired() {
+                    cookies.remove(&cookie.name);
+                } else {
+                    cookies.insert(cookie.name, Value::String(cookie.value));
+                }
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let failed = |err: std::io::Error| {
+            format!("Could not save session to {}: {}", self.path.display(), err)
+        };
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(failed)?;
+        }
+        let mut contents = Map::new();
+        contents.insert("headers".to_string(), Value::Object(self.headers.clone()));
+        contents.insert(
+            "credentials".to_string(),
+            Value::Object(self.credentials.clone()),
+        );
+        contents.insert("cookies".to_string(), Value::Object(self.cookies.clone()));
+        let contents = serde_json::to_string_pretty(&contents).unwrap() + "\n";
+        write_private(&self.path, contents.as_bytes()).map_err(failed)
+    }
+}
+
+// Sessions hold credentials and cookies, so only the owner may read them
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // The mode only applies to new files
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("yahc-session-{}-{}.json", std::process::id(), name))
+    }
+
+    fn header_names(names: &[&'static str]) -> Vec<HeaderName> {
+        names
+            .iter()
+            .map(|name| HeaderName::from_static(name))
+            .collect()
+    }
+
+    #[test]
+    fn load_session_files() {
+        let path = temp_path("load");
+        let session = Session::from_file(&path).unwrap();
+        assert!(session.headers.is_empty() && session.cookies.is_empty());
+
+        fs::write(
+            &path,
+            r#"{"headers": {"x-api": "1"}, "cookies": {"example.com:80": {"id": "a"}}}"#,
+        )
+        .unwrap();
+        let session = Session::from_file(&path).unwrap();
+        assert_eq!(session.headers["x-api"], "1");
+        assert!(session.credentials.is_empty());
+        assert_eq!(session.cookies["example.com:80"]["id"], "a");
+
+        fs::write(&path, r#"{"headers": []}"#).unwrap();
+        let err = Session::from_file(&path).err().unwrap();
+        assert!(err.ends_with(r#""headers" must be an object"#), "{}", err);
+        fs::write(&path, "{").unwrap();
+        assert!(Session::from_file(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn merge_session_into_request() {
+        let path = temp_path("merge");
+        fs::write(
+            &path,
+            r#"{
+                "headers": {"x-api": "saved", "x-other": "saved"},
+                "credentials": {"example.com:80": "Bearer saved"},
+                "cookies": {"example.com:80": {"id": "saved", "theme": "dark"}}
+            }"#,
+        )
+        .unwrap();
+        let session = Session::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let url = Url::parse("http://example.com/").unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api", HeaderValue::from_static("given"));
+        headers.insert(COOKIE, HeaderValue::from_static("id=given"));
+        let given = header_names(&["x-api", "cookie"]);
+        session.apply(&mut headers, &given, &url, false).unwrap();
+        assert_eq!(headers["x-api"], "given");
+        assert_eq!(headers["x-other"], "saved");
+        assert_eq!(headers[AUTHORIZATION], "Bearer saved");
+        assert_eq!(headers[COOKIE], "id=given; theme=dark");
+
+        // --auth wins over saved credentials
+        let mut headers = HeaderMap::new();
+        session.apply(&mut headers, &[], &url, true).unwrap();
+        assert!(!headers.contains_key(AUTHORIZATION));
+        assert_eq!(headers[COOKIE], "id=saved; theme=dark");
+
+        // Nothing host-specific goes to another port
+        let mut headers = HeaderMap::new();
+        let other = Url::parse("http://example.com:8080/").unwrap();
+        session.apply(&mut headers, &[], &other, false).unwrap();
+        assert!(!headers.contains_key(AUTHORIZATION));
+        assert!(!headers.contains_key(COOKIE));
+        assert_eq!(headers["x-api"], "saved");
+    }
+
+    #[test]
+    fn save_and_reload_session() {
+        let path = temp_path("save");
+        let mut session = Session::from_file(&path).unwrap();
+        let url = Url::parse("https://example.com/login").unwrap();
+
+        let mut sent = HeaderMap::new();
+        sent.insert("x-api", HeaderValue::from_static("1"));
+        sent.insert("content-type", HeaderValue::from_static("text/plain"));
+        sent.insert("user-agent", HeaderValue::from_static("yahc"));
+        sent.insert(AUTHORIZATION, HeaderValue::from_static("Basic dTpw"));
+        let given = header_names(&["x-api", "content-type"]);
+        let mut hops = Hops::default();
+        for set_cookie in &["id=1; Path=/", "old=; Max-Age=0"] {
+            let set_cookie = HeaderValue::from_static(set_cookie);
+            hops.set_cookies.push((url.clone(), set_cookie));
+        }
+        session.cookies.insert(
+            "example.com:443".to_string(),
+            serde_json::json!({"old": "x"}),
+        );
+        session.update(&sent, &given, &url, &hops);
+        session.save().unwrap();
+
+        let saved = Session::from_file(&path).unwrap();
+        assert_eq!(
+            Value::Object(saved.headers),
+            serde_json::json!({"x-api": "1"})
+        );
+        assert_eq!(
+            Value::Object(saved.credentials),
+            serde_json::json!({"example.com:443": "Basic dTpw"})
+        );
+        assert_eq!(
+            Value::Object(saved.cookies),
+            serde_json::json!({"example.com:443": {"id": "1"}})
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            // Files saved before keep nothing of their old mode either
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+            session.save().unwrap();
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::blocking::Request;
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use sha2::{Digest, Sha256};
+
+use crate::utils::hex;
+
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    pub fn new(
+        access_key_id: Option<String>,
+        secret_access_key: Option<String>,
+        session_token: Option<String>,
+    ) -> Result<AwsCredentials, &'static str> {
+        match (access_key_id, secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(AwsCredentials {
+                access_key_id,
+                secret_access_key,
+                session_token,
+            }),
+            _ => Err("--aws-sigv4 needs an access key id and a secret access key, set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"),
+        }
+    }
+}
+
+// https://docs.aws.amazon.com/general/latest/gr/sigv4_signing.html
+pub fn sign_aws_sigv4(
+    request: &mut Request,
+    service_region: &str,
+    credentials: &AwsCredentials,
+) -> Result<(), String> {
+    sign_aws_sigv4_at(request, service_region, credentials, Utc::now())
+}
+
+fn sign_aws_sigv4_at(
+    request: &mut Request,
+    service_region: &str,
+    credentials: &AwsCredentials,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    let mut parts = service_region.splitn(2, ':');
+    let (service, region) = match (parts.next(), parts.next()) {
+        (Some(service), Some(region)) if !service.is_empty() && !region.is_empty() => {
+            (service, region)
+        }
+        _ => {
+            return Err(format!(
+                "{:?} is not a valid --aws-sigv4 value, expected SERVICE:REGION",
+                service_region
+            ))
+        }
+    };
+    let payload = match request.body() {
+        Some(body) => match body.as_bytes() {
+            Some(bytes) => bytes.to_vec(),
+            None => return Err("Streaming request bodies can't be signed with --aws-sigv4".into()),
+        },
+        None => vec![],
+    };
+
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    let headers = request.headers_mut();
+    headers.insert("x-amz-date", HeaderValue::from_str(&amz_date).unwrap());
+    if let Some(token) = &credentials.session_token {
+        let token = HeaderValue::from_str(token).map_err(|_| "Invalid AWS session token")?;
+        headers.insert("x-amz-security-token", token);
+    }
+    headers.remove(AUTHORIZATION);
+
+    let canonical_request = canonical_request(request, &payload);
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let secret = format!("AWS4{}", credentials.secret_access_key);
+    let key = hmac_sha256(secret.as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    let key = hmac_sha256(&key, b"aws4_request");
+    let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id,
+        scope,
+        signed_headers(request).join(";"),
+        signature
+    );
+    let authorization =
+        HeaderValue::from_str(&authorization).map_err(|_| "Invalid AWS access key id")?;
+    request.headers_mut().insert(AUTHORIZATION, authorization);
+    Ok(())
+}
+
+fn canonical_request(request: &Request, payload: &[u8]) -> String {
+    let url = request.url();
+
+    let mut query: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, value)| (uri_encode(&key), uri_encode(&value)))
+        .collect();
+    query.sort();
+    let query: Vec<String> = query
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+
+    let mut canonical_headers = String::new();
+    for name in signed_headers(request) {
+        let values: Vec<String> = request
+            .headers()
+            .get_all(name.as_str())
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).trim().to_string())
+            .collect();
+        writeln!(canonical_headers, "{}:{}", name, values.join(",")).unwrap();
+    }
+
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method(),
+        url.path(),
+        query.join("&"),
+        canonical_headers,
+        signed_headers(request).join(";"),
+        hex(&Sha256::digest(payload))
+    )
+}
+
+fn signed_headers(request: &Request) -> Vec<String> {
+    let mut names: Vec<String> = request
+        .headers()
+        .keys()
+        .filter(|name| *name != AUTHORIZATION)
+        .map(HeaderName::to_string)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn uri_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => write!(encoded, "%{:02X}", byte).unwrap(),
+        }
+    }
+    encoded
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use reqwest::header::{CONTENT_TYPE, HOST};
+    use reqwest::Method;
+
+    use super::*;
+
+    // The example GET request from the "Signing AWS requests with Signature
+    // Version 4" documentation
+    #[test]
+    fn documented_get_example() {
+        let url = "https://iam.amazonaws.com/?Action=ListUsers&Version=2010-05-08";
+        let mut request = Request::new(Method::GET, url.parse().unwrap());
+        let headers = request.headers_mut();
+        headers.insert(HOST, HeaderValue::from_static("iam.amazonaws.com"));
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded; charset=utf-8"),
+        );
+        let credentials = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        let now = Utc.ymd(2015, 8, 30).and_hms(12, 36, 0);
+
+        sign_aws_sigv4_at(&mut request, "iam:us-east-1", &credentials, now).unwrap();
+
+        assert_eq!(
+            request.headers()[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 \
+             Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+        assert_eq!(request.headers()["x-amz-date"], "20150830T123600Z");
+    }
+
+    #[test]
+    fn invalid_service_region() {
+        let mut request = Request::new(Method::GET, "https://example.com".parse().unwrap());
+        let credentials = AwsCredentials::new(Some("id".into()), Some("key".into()), None).unwrap();
+        assert!(sign_aws_sigv4_at(&mut request, "s3", &credentials, Utc::now()).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::env;
+
+use serde_json::Value;
+
+// A small subset of mustache: `{{name}}` and `{{nested.name}}` are replaced with values
+// from `data`, strings are inserted as-is and anything else as JSON.
+pub fn render(template: &str, data: &Value, allow_missing: bool) -> Result<String, String> {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => return Err("Unclosed {{ in template".to_string()),
+        };
+        let name = rest[start + 2..end].trim();
+        match lookup(data, name) {
+            Some(Value::String(value)) => output.push_str(value),
+            Some(value) => output.push_str(&value.to_string()),
+            None if allow_missing => {}
+            None => return Err(format!("Missing template variable {:?}", name)),
+        }
+        rest = &rest[end + 2..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn lookup<'a>(data: &'a Value, name: &str) -> Option<&'a Value> {
+    name.split('.').try_fold(data, |value, key| value.get(key))
+}
+
+// Replaces `${NAME}` with the environment variable NAME, or `${NAME:-default}`
+// with `default` when NAME is unset or empty. `$${` is a literal `${`.
+pub fn expand_env(text: &str) -> Result<String, String> {
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            output.push_str(&rest[..start - 1]);
+            output.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        output.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("Unclosed ${{ in {:?}", text)),
+        };
+        let variable = &rest[start + 2..end];
+        let (name, default) = match variable.find(":-") {
+            Some(index) => (&variable[..index], Some(&variable[index + 2..])),
+            None => (variable, None),
+        };
+        match (env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => output.push_str(default),
+            (Ok(value), _) => output.push_str(&value),
+            (Err(_), Some(default)) => output.push_str(default),
+            (Err(_), None) => {
+                return Err(format!("Environment variable {} is not set", name));
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn render_values() {
+        let data = json!({"name": "yahc", "user": {"id": 7, "tags": ["a"]}});
+        assert_eq!(
+            render("{{ name }}: {{user.id}} {{user.tags}}", &data, false),
+            Ok("yahc: 7 [\"a\"]".to_string())
+        );
+    }
+
+    #[test]
+    fn render_missing() {
+        let data = json!({});
+        assert_eq!(
+            render("a{{missing}}b", &data, false),
+            Err("Missing template variable \"missing\"".to_string())
+        );
+        assert_eq!(render("a{{missing}}b", &data, true), Ok("ab".to_string()));
+        assert_eq!(
+            render("{{name", &data, false),
+            Err("Unclosed {{ in template".to_string())
+        );
+    }
+
+    #[test]
+    fn expand_env_variables() {
+        env::set_var("YAHC_TEST_SET", "value");
+        env::set_var("YAHC_TEST_EMPTY", "");
+        env::remove_var("YAHC_TEST_UNSET");
+        assert_eq!(
+            expand_env("a ${YAHC_TEST_SET} ${YAHC_TEST_EMPTY:-x} ${YAHC_TEST_UNSET:-y} $${z}"),
+            Ok("a value x y ${z}".to_string())
+        );
+        assert_eq!(
+            expand_env("${YAHC_TEST_UNSET}"),
+            Err("Environment variable YAHC_TEST_UNSET is not set".to_string())
+        );
+        assert_eq!(
+            expand_env("${YAHC_TEST_SET"),
+            Err("Unclosed ${ in \"${YAHC_TEST_SET\"".to_string())
+        );
+    }
+}
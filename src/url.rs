@@ -3,17 +3,20 @@ use regex::Regex;
 pub struct Url(pub reqwest::Url);
 
 impl Url {
-    pub fn new(url: String, default_scheme: Option<String>) -> Url {
+    pub fn new(url: String, default_scheme: Option<String>) -> Result<Url, String> {
         let default_scheme = default_scheme.unwrap_or("http://".to_string());
         let re = Regex::new("[a-zA-Z]://.+").unwrap();
-        if url.starts_with(":") {
-            let url = format!("{}{}{}", default_scheme, "localhost", url);
-            Url(reqwest::Url::parse(&url).unwrap().into())
+        let full_url = if url.starts_with(":") {
+            format!("{}{}{}", default_scheme, "localhost", url)
         } else if !re.is_match(&url) {
-            let url = format!("{}{}", default_scheme, url);
-            Url(reqwest::Url::parse(&url).unwrap().into())
+            format!("{}{}", default_scheme, url)
         } else {
-            Url(reqwest::Url::parse(&url).unwrap())
+            url.clone()
+        };
+        match reqwest::Url::parse(&full_url) {
+            Ok(parsed) if parsed.host().is_some() => Ok(Url(parsed)),
+            Ok(_) => Err(format!("{:?} is not a valid URL: it has no host", url)),
+            Err(err) => Err(format!("{:?} is not a valid URL: {}", url, err)),
         }
     }
 
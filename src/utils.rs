@@ -1,47 +1,488 @@
+use std::error::Error;
 use std::fmt::Write;
+use std::fs;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use ansi_term::Color::{self, Fixed, RGB};
 use ansi_term::{self, Style};
-use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use encoding_rs::{Encoding, UTF_8};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use mime::Mime;
+use rand::seq::SliceRandom;
+use reqwest::blocking::Response;
+use reqwest::header::{HeaderMap, CONTENT_ENCODING, CONTENT_TYPE};
 use syntect::dumps::from_binary;
 use syntect::easy::HighlightLines;
-use syntect::highlighting::{FontStyle, ThemeSet};
-use syntect::parsing::SyntaxSet;
+use syntect::highlighting::{FontStyle, Highlighter, ThemeSet};
+use syntect::parsing::{Scope, SyntaxSet};
 use syntect::util::LinesWithEndings;
 
-use crate::Theme;
+use crate::{Theme, Timeout};
 
 // TODO: return enum
 pub fn get_content_type(headers: &HeaderMap) -> Option<&str> {
     headers.get(CONTENT_TYPE)?.to_str().ok()
 }
 
+// Runs through the shell so that pipes and quoting work as they would in a script
+pub fn run_command(command: &str) -> Result<String, String> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let output = Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| format!("Failed to run {:?}: {}", command, err))?;
+    if !output.status.success() {
+        return Err(format!("{:?} failed with {}", command, output.status));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| format!("{:?} printed output that is not valid UTF-8", command))
+}
+
+// Every encoding listed here has to be decoded by decode_body
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br, zstd";
+
+// The size of a response body as it was received and after decoding it
+pub struct BodySize {
+    pub received: usize,
+    pub decoded: usize,
+    pub encoded: bool,
+}
+
+pub fn read_body(
+    response: Response,
+    stall_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    read_sized_body(response, stall_timeout, deadline).map(|(body, _)| body)
+}
+
+pub fn read_sized_body(
+    response: Response,
+    stall_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+) -> Result<(Vec<u8>, BodySize), Box<dyn Error>> {
+    let headers = response.headers().clone();
+    let body = read_raw_body(response, stall_timeout, deadline)?;
+    let received = body.len();
+    let body = decode_body(&headers, body)?;
+    let size = BodySize {
+        received,
+        decoded: body.len(),
+        encoded: headers.contains_key(CONTENT_ENCODING),
+    };
+    Ok((body, size))
+}
+
+// reqwest is built without its own decoders, so that the received size stays known
+pub fn decode_body(headers: &HeaderMap, body: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let encoding = match headers.get(CONTENT_ENCODING) {
+        Some(encoding) => encoding.to_str().unwrap_or("").trim().to_ascii_lowercase(),
+        None => return Ok(body),
+    };
+
+    let mut decoded = vec![];
+    match encoding.as_str() {
+        "gzip" | "x-gzip" => {
+            GzDecoder::new(&body[..]).read_to_end(&mut decoded)?;
+        }
+        "br" => {
+            brotli::Decompressor::new(&body[..], 4096).read_to_end(&mut decoded)?;
+        }
+        // Servers disagree on whether deflate means zlib-wrapped or raw deflate data
+        "deflate" => {
+            if ZlibDecoder::new(&body[..])
+                .read_to_end(&mut decoded)
+                .is_err()
+            {
+                decoded.clear();
+                DeflateDecoder::new(&body[..]).read_to_end(&mut decoded)?;
+            }
+        }
+        "zstd" => {
+            zstd::stream::read::Decoder::new(&body[..])?.read_to_end(&mut decoded)?;
+        }
+        _ => return Ok(body),
+    }
+    Ok(decoded)
+}
+
+// Reads happen on a separate thread so that a stalled read can be abandoned
+pub fn read_raw_body(
+    mut response: Response,
+    stall_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    if stall_timeout.is_none() && deadline.is_none() {
+        return Ok(response.bytes()?.to_vec());
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 8192];
+        loop {
+            let chunk = response.read(&mut buffer).map(|n| buffer[..n].to_vec());
+            let done = !matches!(&chunk, Ok(chunk) if !chunk.is_empty());
+            if sender.send(chunk).is_err() || done {
+                break;
+            }
+        }
+    });
+
+    let mut body = vec![];
+    loop {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let wait = match (stall_timeout, remaining) {
+            (Some(stall_timeout), Some(remaining)) => stall_timeout.min(remaining),
+            (stall_timeout, remaining) => stall_timeout.or(remaining).unwrap(),
+        };
+        match receiver.recv_timeout(wait) {
+            Ok(Ok(chunk)) if chunk.is_empty() => return Ok(body),
+            Ok(Ok(chunk)) => body.extend(chunk),
+            Ok(Err(err)) => return Err(err.into()),
+            Err(RecvTimeoutError::Timeout) if wait == remaining.unwrap_or_default() => {
+                return Err(Box::new(Timeout::Read))
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(format!(
+                    "Response body stalled: no data received for {} seconds",
+                    wait.as_secs_f64()
+                )
+                .into())
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err("Reading the response body failed".into())
+            }
+        }
+    }
+}
+
+// Returns the headers and body of every part, see RFC 2046 section 5.1.1
+pub fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<(&'a [u8], &'a [u8])> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    let find = |from: usize| {
+        body.get(from..)?
+            .windows(delimiter.len())
+            .position(|window| window == delimiter)
+            .map(|position| from + position)
+    };
+
+    let mut parts = vec![];
+    let mut start = match find(0) {
+        Some(start) => start + delimiter.len(),
+        None => return parts,
+    };
+    while !body[start..].starts_with(b"--") {
+        // Skip transport padding up to the end of the delimiter line
+        let content_start = match body[start..].iter().position(|&b| b == b'\n') {
+            Some(newline) => start + newline + 1,
+            None => break,
+        };
+        let end = match find(content_start) {
+            Some(end) => end,
+            None => break,
+        };
+        // The line break before a delimiter belongs to the delimiter
+        let mut content = &body[content_start..end];
+        if content.ends_with(b"\n") {
+            content = &content[..content.len() - 1];
+        }
+        if content.ends_with(b"\r") {
+            content = &content[..content.len() - 1];
+        }
+
+        // A part that starts with a blank line has no headers
+        let (headers, part_body) = if content.starts_with(b"\r\n") {
+            (&content[..0], &content[2..])
+        } else if content.starts_with(b"\n") {
+            (&content[..0], &content[1..])
+        } else if let Some(i) = find_subslice(content, b"\r\n\r\n") {
+            (&content[..i], &content[i + 4..])
+        } else if let Some(i) = find_subslice(content, b"\n\n") {
+            (&content[..i], &content[i + 2..])
+        } else {
+            (content, &content[..0])
+        };
+        parts.push((headers, part_body));
+        start = end + delimiter.len();
+    }
+    parts
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// Same decoding as reqwest's Response::text
+pub fn decode_text(body: &[u8], content_type: &str) -> String {
+    let encoding = content_type
+        .parse::<Mime>()
+        .ok()
+        .and_then(|mime| mime.get_param("charset").map(|charset| charset.to_string()))
+        .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+        .unwrap_or(UTF_8);
+    encoding.decode(body).0.into_owned()
+}
+
+// Blank lines and lines starting with # are skipped
+pub fn random_user_agent(path: &str) -> Result<String, String> {
+    let content =
+        fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+    let user_agents: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    match user_agents.choose(&mut rand::thread_rng()) {
+        Some(user_agent) => Ok(user_agent.to_string()),
+        None => Err(format!("{} does not contain any user agents", path)),
+    }
+}
+
 pub fn indent_json(text: &str) -> String {
     let mut fmt = jsonxf::Formatter::pretty_printer();
     fmt.indent = String::from("    ");
     fmt.format(text).unwrap()
 }
 
+enum XmlToken<'a> {
+    Open(&'a str),
+    Close(&'a str),
+    // Self-closing tags, comments, CDATA sections, declarations and doctypes
+    Other(&'a str),
+    Text(&'a str),
+}
+
+fn tokenize_xml(text: &str) -> Option<Vec<XmlToken<'_>>> {
+    let mut tokens = vec![];
+    let mut rest = text;
+    while !rest.is_empty() {
+        if !rest.starts_with('<') {
+            let end = rest.find('<').unwrap_or(rest.len());
+            tokens.push(XmlToken::Text(&rest[..end]));
+            rest = &rest[end..];
+            continue;
+        }
+        let end = if rest.starts_with("<!--") {
+            rest.find("-->")? + 3
+        } else if rest.starts_with("<![CDATA[") {
+            rest.find("]]>")? + 3
+        } else if rest.starts_with("<?") {
+            rest.find("?>")? + 2
+        } else {
+            // `>` may appear in quoted attribute values
+            let mut quote = None;
+            let (end, _) = rest.char_indices().find(|&(_, c)| match quote {
+                Some(q) if c == q => {
+                    quote = None;
+                    false
+                }
+                Some(_) => false,
+                None if c == '"' || c == '\'' => {
+                    quote = Some(c);
+                    false
+                }
+                None => c == '>',
+            })?;
+            end + 1
+        };
+        let tag = &rest[..end];
+        tokens.push(if tag.starts_with("</") {
+            XmlToken::Close(tag)
+        } else if tag.starts_with("<!") || tag.starts_with("<?") || tag.ends_with("/>") {
+            XmlToken::Other(tag)
+        } else {
+            XmlToken::Open(tag)
+        });
+        rest = &rest[end..];
+    }
+    Some(tokens)
+}
+
+// Puts every element on its own line, except that elements which only contain
+// text stay on one line. Text is trimmed but CDATA sections are kept as they are.
+// Anything that can't be tokenized is returned unchanged.
+pub fn indent_xml(text: &str) -> String {
+    let tokens = match tokenize_xml(text) {
+        Some(tokens) => tokens,
+        None => return text.to_string(),
+    };
+    let mut output = String::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        let indent = "    ".repeat(depth);
+        match (&tokens[i], tokens.get(i + 1), tokens.get(i + 2)) {
+            (XmlToken::Text(text), _, _) if text.trim().is_empty() => {}
+            (XmlToken::Open(open), Some(XmlToken::Text(text)), Some(XmlToken::Close(close))) => {
+                writeln!(output, "{}{}{}{}", indent, open, text.trim(), close).unwrap();
+                i += 2;
+            }
+            (XmlToken::Open(open), Some(XmlToken::Close(close)), _) => {
+                writeln!(output, "{}{}{}", indent, open, close).unwrap();
+                i += 1;
+            }
+            (XmlToken::Open(open), _, _) => {
+                writeln!(output, "{}{}", indent, open).unwrap();
+                depth += 1;
+            }
+            (XmlToken::Close(close), _, _) => {
+                depth = depth.saturating_sub(1);
+                writeln!(output, "{}{}", "    ".repeat(depth), close).unwrap();
+            }
+            (XmlToken::Other(other), _, _) => writeln!(output, "{}{}", indent, other).unwrap(),
+            (XmlToken::Text(text), _, _) => writeln!(output, "{}{}", indent, text.trim()).unwrap(),
+        }
+        i += 1;
+    }
+    output.pop();
+    output
+}
+
+// Expects the output of indent_json, where every scalar value is on its own line
+pub fn annotate_types(text: &str) -> String {
+    let lines: Vec<String> = text
+        .split('\n')
+        .map(|line| {
+            let item = line.trim().trim_end_matches(',');
+            let value = match item.strip_prefix('"').map(|rest| &rest[string_end(rest)..]) {
+                Some(after_key) if after_key.starts_with(':') => after_key[1..].trim_start(),
+                _ => item,
+            };
+            let value_type = match value.chars().next() {
+                Some('"') => "string",
+                Some('t') | Some('f') => "boolean",
+                Some('n') => "null",
+                Some('-') | Some('0'..='9') => "number",
+                _ => return line.to_string(),
+            };
+            format!("{} // {}", line, value_type)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+// Cuts string values, but not keys, down to `max` characters and notes how
+// long they were inside the string so that the output is still valid JSON
+pub fn truncate_strings(text: &str, max: usize) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('"') {
+        output.push_str(&rest[..=start]);
+        let string = &rest[start + 1..];
+        let end = string_end(string);
+        let is_key = string[end..].trim_start().starts_with(':');
+
+        // Escape sequences count as one character and are never split
+        let mut length = 0;
+        let mut cut = None;
+        let mut chars = string[..end.saturating_sub(1)].char_indices();
+        while let Some((i, c)) = chars.next() {
+            if length == max {
+                cut = Some(i);
+            }
+            if c == '\\' {
+                let skip = if chars.next().map(|(_, c)| c) == Some('u') {
+                    4
+                } else {
+                    0
+                };
+                chars.by_ref().take(skip).for_each(drop);
+            }
+            length += 1;
+        }
+        match cut {
+            Some(cut) if !is_key => {
+                write!(output, "{}… ({} characters)\"", &string[..cut], length).unwrap();
+            }
+            _ => output.push_str(&string[..end]),
+        }
+        rest = &string[end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+// Index just past the closing quote of a JSON string whose opening quote was already consumed
+fn string_end(text: &str) -> usize {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return i + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Same layout as `xxd`: offset, 16 bytes in groups of two and their ASCII form
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        write!(dump, "{:08x}: ", i * 16).unwrap();
+        for j in 0..16 {
+            match chunk.get(j) {
+                Some(byte) => write!(dump, "{:02x}", byte).unwrap(),
+                None => dump.push_str("  "),
+            }
+            if j % 2 == 1 {
+                dump.push(' ');
+            }
+        }
+        dump.push(' ');
+        for &byte in chunk {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                dump.push(byte as char);
+            } else {
+                dump.push('.');
+            }
+        }
+        dump.push('\n');
+    }
+    dump
+}
+
+lazy_static! {
+    static ref TS: ThemeSet = from_binary(include_bytes!(concat!(
+        env!("OUT_DIR"),
+        "/themepack.themedump"
+    )));
+    static ref PS: SyntaxSet =
+        from_binary(include_bytes!(concat!(env!("OUT_DIR"), "/syntax.packdump")));
+}
+
+fn syntect_theme(theme: &Theme) -> &'static syntect::highlighting::Theme {
+    match theme {
+        Theme::Auto => &TS.themes["ansi"],
+        Theme::Solarized => &TS.themes["solarized"],
+    }
+}
+
 pub fn colorize<'a>(
     text: &'a str,
     syntax: &str,
     theme: &Theme,
 ) -> impl Iterator<Item = String> + 'a {
-    lazy_static! {
-        static ref TS: ThemeSet = ThemeSet::from(from_binary(include_bytes!(concat!(
-            env!("OUT_DIR"),
-            "/themepack.themedump"
-        ))));
-        static ref PS: SyntaxSet = SyntaxSet::from(from_binary(include_bytes!(concat!(
-            env!("OUT_DIR"),
-            "/syntax.packdump"
-        ))));
-    }
     let syntax = PS.find_syntax_by_extension(syntax).unwrap();
-    let mut h = match theme {
-        Theme::Auto => HighlightLines::new(syntax, &TS.themes["ansi"]),
-        Theme::Solarized => HighlightLines::new(syntax, &TS.themes["solarized"]),
-    };
+    let mut h = HighlightLines::new(syntax, syntect_theme(theme));
 
     LinesWithEndings::from(text).map(move |line| {
         let mut s: String = String::new();
@@ -57,6 +498,14 @@ pub fn colorize<'a>(
     })
 }
 
+// The foreground color the theme gives to `scope`, for text that isn't highlighted
+// through a syntax such as the status line
+pub fn scope_color(scope: &str, theme: &Theme) -> Color {
+    let highlighter = Highlighter::new(syntect_theme(theme));
+    let scope = Scope::new(scope).unwrap();
+    to_ansi_color(highlighter.style_for_stack(&[scope]).foreground)
+}
+
 // https://github.com/sharkdp/bat/blob/3a85fd767bd1f03debd0a60ac5bc08548f95bc9d/src/terminal.rs
 fn to_ansi_color(color: syntect::highlighting::Color) -> ansi_term::Color {
     if color.a == 0 {
@@ -91,3 +540,144 @@ fn to_ansi_color(color: syntect::highlighting::Color) -> ansi_term::Color {
         RGB(color.r, color.g, color.b)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_content_encodings() {
+        use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use reqwest::header::HeaderValue;
+        use std::io::Write;
+
+        let text = b"hello hello hello hello".to_vec();
+        let headers = |encoding| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+            headers
+        };
+
+        let mut gzip = GzEncoder::new(vec![], Compression::default());
+        gzip.write_all(&text).unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert_eq!(decode_body(&headers("gzip"), gzip).unwrap(), text);
+
+        let mut brotli = vec![];
+        let mut encoder = brotli::CompressorReader::new(&text[..], 4096, 5, 22);
+        encoder.read_to_end(&mut brotli).unwrap();
+        assert_eq!(decode_body(&headers("br"), brotli).unwrap(), text);
+
+        let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+        zlib.write_all(&text).unwrap();
+        let zlib = zlib.finish().unwrap();
+        assert_eq!(decode_body(&headers("deflate"), zlib).unwrap(), text);
+
+        let mut deflate = DeflateEncoder::new(vec![], Compression::default());
+        deflate.write_all(&text).unwrap();
+        let deflate = deflate.finish().unwrap();
+        assert_eq!(decode_body(&headers("Deflate"), deflate).unwrap(), text);
+
+        let zstd = zstd::stream::encode_all(&text[..], 0).unwrap();
+        assert_eq!(decode_body(&headers("zstd"), zstd).unwrap(), text);
+        assert!(decode_body(&headers("zstd"), text.clone()).is_err());
+
+        // Bodies without a known encoding are left alone
+        assert_eq!(decode_body(&HeaderMap::new(), text.clone()).unwrap(), text);
+        assert_eq!(
+            decode_body(&headers("identity"), text.clone()).unwrap(),
+            text
+        );
+    }
+
+    #[test]
+    fn hexdump_pads_the_last_line() {
+        let bytes: Vec<u8> = (0x41..0x41 + 18).collect();
+        assert_eq!(
+            hexdump(&bytes),
+            "00000000: 4142 4344 4546 4748 494a 4b4c 4d4e 4f50  ABCDEFGHIJKLMNOP\n\
+             00000010: 5152                                     QR\n"
+        );
+        assert_eq!(
+            hexdump(b"\x00\n ~"),
+            "00000000: 000a 207e                                .. ~\n"
+        );
+        assert_eq!(hexdump(b""), "");
+    }
+
+    #[test]
+    fn truncate_long_strings() {
+        assert_eq!(
+            truncate_strings(r#"{"long key": "abcdef", "b": "abc", "c": 12345}"#, 3),
+            r#"{"long key": "abc… (6 characters)", "b": "abc", "c": 12345}"#
+        );
+        // Escape sequences are never split
+        assert_eq!(
+            truncate_strings(r#"["a\"bcd", "\u00e9\u00e9\u00e9\u00e9"]"#, 2),
+            r#"["a\"… (5 characters)", "\u00e9\u00e9… (4 characters)"]"#
+        );
+    }
+
+    #[test]
+    fn indent_nested_xml() {
+        let xml = r#"<?xml version="1.0"?><a x="1>2"><b>text</b><c/><d></d><!-- note --></a>"#;
+        let expected = [
+            r#"<?xml version="1.0"?>"#,
+            r#"<a x="1>2">"#,
+            r#"    <b>text</b>"#,
+            r#"    <c/>"#,
+            r#"    <d></d>"#,
+            r#"    <!-- note -->"#,
+            r#"</a>"#,
+        ];
+        assert_eq!(indent_xml(xml), expected.join("\n"));
+        // Unterminated markup is left alone
+        assert_eq!(indent_xml("<a><!-- b"), "<a><!-- b");
+    }
+
+    #[test]
+    fn split_multipart_parts() {
+        let body = b"preamble\r\n--xyz\r\nContent-Type: text/plain\r\n\r\nhello\r\n\
+                     --xyz  \r\n\r\nno headers\r\n--xyz--\r\nepilogue";
+        assert_eq!(
+            split_multipart(body, "xyz"),
+            vec![
+                (&b"Content-Type: text/plain"[..], &b"hello"[..]),
+                (&b""[..], &b"no headers"[..]),
+            ]
+        );
+        assert!(split_multipart(b"no delimiter", "xyz").is_empty());
+    }
+
+    #[test]
+    fn annotate_types_of_values() {
+        let json = [
+            r#"{"#,
+            r#"    "a": 1,"#,
+            r#"    "b": ["#,
+            r#"        true,"#,
+            r#"        null"#,
+            r#"    ],"#,
+            r#"    "c": {"#,
+            r#"        "d": "x:y","#,
+            r#"        "e\"": -2.5"#,
+            r#"    }"#,
+            r#"}"#,
+        ];
+        let expected = [
+            r#"{"#,
+            r#"    "a": 1, // number"#,
+            r#"    "b": ["#,
+            r#"        true, // boolean"#,
+            r#"        null // null"#,
+            r#"    ],"#,
+            r#"    "c": {"#,
+            r#"        "d": "x:y", // string"#,
+            r#"        "e\"": -2.5 // number"#,
+            r#"    }"#,
+            r#"}"#,
+        ];
+        assert_eq!(annotate_types(&json.join("\n")), expected.join("\n"));
+    }
+}